@@ -0,0 +1,13 @@
+use iridium::cli::Args;
+
+// Same options as `iridium --headless`, for machines where that's the only
+// mode that makes sense.
+fn main() {
+    env_logger::init();
+    let args = Args::try_parse_headless_from(std::env::args_os()).unwrap_or_else(|e| e.exit());
+
+    if let Err(e) = iridium::cli::run_headless(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
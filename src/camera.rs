@@ -1,98 +1,449 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use glam::{Vec3, Quat, Vec4, Mat4};
+use glam::{Vec2, Vec3, Mat4, Quat};
 
-#[derive(Clone, Copy)]
+/// Radians turned per pixel of mouse movement.
+const MOUSE_SENSITIVITY: f32 = 0.0025;
+/// Radians per second turned by the keyboard.
+const TURN_RATE: f32 = 1.5;
+/// Stops short of straight up or down, where yaw stops meaning anything.
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+/// Fly speed, in world units per second, before any modifiers.
+const DEFAULT_SPEED: f32 = 1.5;
+const MIN_SPEED: f32 = 0.05;
+const MAX_SPEED: f32 = 100.0;
+/// How much one notch of the scroll wheel changes the fly speed.
+const SPEED_STEP: f32 = 1.2;
+const SPRINT_FACTOR: f32 = 4.0;
+const SLOW_FACTOR: f32 = 0.25;
+/// How much one notch of the scroll wheel moves an orbiting camera in.
+const ZOOM_STEP: f32 = 0.9;
+const MIN_DISTANCE: f32 = 0.1;
+/// How long holding forward or back takes to zoom as far as a notch of the
+/// scroll wheel.
+const SECONDS_PER_ZOOM_STEP: f32 = 0.1;
+const MAX_DISTANCE: f32 = 1000.0;
+/// How far dragging across the whole window pans, relative to the distance
+/// to the pivot.
+const PAN_SENSITIVITY: f32 = 0.0015;
+/// Where a chasing camera sits by default, in the followed boid's frame
+/// (+Z ahead, +Y up): a little above and a couple of boid lengths behind.
+pub(crate) const DEFAULT_CHASE_OFFSET: Vec3 = glam::const_vec3!([0.0, 0.25, -1.2]);
+/// Where a camera riding along sits, in the followed boid's frame: just
+/// above the boid's back so its own mesh doesn't fill the view.
+const RIDE_OFFSET: Vec3 = glam::const_vec3!([0.0, 0.08, 0.0]);
+/// How far ahead of the boid a camera riding along looks.
+const RIDE_LOOK_AHEAD: f32 = 1.0;
+pub(crate) const MIN_FOV: f32 = 10.0;
+pub(crate) const MAX_FOV: f32 = 150.0;
+/// Roughly how long the camera takes to catch up with where it's been
+/// told to go.
+const SMOOTHING: f32 = 0.008_333_33;
+
+/// The controls held down this frame.
+#[derive(Clone, Copy, Default)]
 pub(crate) struct Movement {
     pub(crate) strafe_forward: bool,
     pub(crate) strafe_back: bool,
     pub(crate) strafe_left: bool,
     pub(crate) strafe_right: bool,
+    pub(crate) up: bool,
+    pub(crate) down: bool,
     pub(crate) pitch_up: bool,
     pub(crate) pitch_down: bool,
     pub(crate) yaw_left: bool,
     pub(crate) yaw_right: bool,
+    pub(crate) sprint: bool,
+    pub(crate) slow: bool,
+}
+
+/// How the camera moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum CameraMode {
+    /// Flies freely, moving relative to where it's looking.
+    Fly,
+    /// Circles a pivot point, always looking at it.
+    Orbit,
+    /// Follows the selected boid from behind, looking at it.
+    Chase,
+    /// Rides along on the selected boid, looking where it's going.
+    FirstPerson,
+}
+
+/// What an orbiting camera circles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrbitTarget {
+    WorldCenter,
+    /// The mean position of every boid.
+    Centroid,
+    /// The boid with this index.
+    Boid(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Projection {
+    Perspective,
+    /// Parallel lines stay parallel, for measuring by eye. The view is as
+    /// tall as the field of view is at the orbit distance, so zooming
+    /// still works.
+    Orthographic,
+}
+
+/// Looking straight along one of the world's axes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum View {
+    /// Down onto the world, with +Z up the screen.
+    Top,
+    /// From +X, looking towards -X.
+    Side,
+    /// From -Z, looking towards +Z.
+    Front,
 }
 
+/// How the camera projects what it sees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Lens {
+    pub(crate) projection: Projection,
+    /// Vertical field of view, in degrees.
+    pub(crate) fov: f32,
+    pub(crate) near: f32,
+    pub(crate) far: f32,
+}
+
+/// Looks along `yaw` and `pitch`. Flying, it moves relative to where it's
+/// looking; orbiting, it sits `distance` back from `looking_at`; following
+/// a boid, it heads for a spot fixed to the boid and looks at `looking_at`.
 pub(crate) struct Camera {
-    fov: f32,
-    near: f32,
-    far: f32,
+    lens: Lens,
     aspect_ratio: f32,
-    rotation: Vec3,
+    /// Radians clockwise from +Z, seen from above.
+    yaw: f32,
+    /// Radians above the horizon.
+    pitch: f32,
     cam_position: Vec3,
     cam_dest: Vec3,
+    /// World units per second.
+    speed: f32,
+    mode: CameraMode,
+    /// The point an orbiting camera circles and looks at. Follows
+    /// `pivot_dest` the same way `cam_position` follows `cam_dest`.
     looking_at: Vec3,
+    pivot_dest: Vec3,
+    /// Where the orbit target is, before panning.
+    target_point: Vec3,
+    /// How far the pivot has been panned away from the target.
+    pan_offset: Vec3,
+    distance: f32,
+    /// Where a chasing camera sits, in the followed boid's frame.
+    chase_offset: Vec3,
     view_mat: Mat4,
+    /// The projection, rebuilt whenever the lens, the window's shape or,
+    /// for an orthographic view, the orbit distance changes.
     perspective_mat: Mat4,
 }
 
 impl Camera {
     pub(crate) fn new(camera_pos: Vec3, camera_target: Vec3, fov: f32, near: f32, far: f32,
         aspect_ratio: f32) -> Self {
-        
-        let perspective_mat = Mat4::perspective_lh(fov.to_radians(), aspect_ratio, near, far);
-        let view_mat = Self::view_matrix(Vec3::ZERO, camera_pos);
-
-        Self {
-            fov,
-            near,
-            far,
+
+        let dir = (camera_target - camera_pos).try_normalize().unwrap_or(Vec3::Z);
+        let yaw = dir.x.atan2(dir.z);
+        let pitch = dir.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+
+        let mut camera = Self {
+            lens: Lens { projection: Projection::Perspective, fov, near, far },
             aspect_ratio,
-            rotation: Vec3::ZERO,
+            yaw,
+            pitch,
             cam_position: camera_pos,
             cam_dest: camera_pos,
+            speed: DEFAULT_SPEED,
+            mode: CameraMode::Fly,
             looking_at: camera_target,
-            view_mat,
-            perspective_mat,
+            pivot_dest: camera_target,
+            target_point: camera_target,
+            pan_offset: Vec3::ZERO,
+            distance: camera_pos.distance(camera_target).max(MIN_DISTANCE),
+            chase_offset: DEFAULT_CHASE_OFFSET,
+            view_mat: Self::view_matrix(camera_pos, yaw, pitch),
+            perspective_mat: Mat4::IDENTITY,
+        };
+        camera.update_projection();
+        camera
+    }
+
+    fn update_projection(&mut self) {
+        let Lens { projection, fov, near, far } = self.lens;
+        self.perspective_mat = match projection {
+            Projection::Perspective => Mat4::perspective_lh(fov.to_radians(), self.aspect_ratio, near, far),
+            // reaching as far behind the eye as in front of it means
+            // nothing between an orbiting camera and its pivot is clipped
+            Projection::Orthographic => {
+                let half_height = self.distance * (0.5 * fov).to_radians().tan();
+                let half_width = half_height * self.aspect_ratio;
+                Mat4::orthographic_lh(-half_width, half_width, -half_height, half_height, -far, far)
+            }
+        };
+    }
+
+    pub(crate) fn lens(&self) -> Lens {
+        self.lens
+    }
+
+    /// Sets the whole lens at once, keeping each part within what the
+    /// setters allow.
+    pub(crate) fn set_lens(&mut self, lens: Lens) {
+        self.lens.projection = lens.projection;
+        self.set_fov(lens.fov);
+        self.set_clip_planes(lens.near, lens.far);
+    }
+
+    pub(crate) fn set_projection(&mut self, projection: Projection) {
+        self.lens.projection = projection;
+        self.update_projection();
+    }
+
+    /// Sets the vertical field of view, in degrees.
+    pub(crate) fn set_fov(&mut self, fov: f32) {
+        self.lens.fov = fov.clamp(MIN_FOV, MAX_FOV);
+        self.update_projection();
+    }
+
+    /// Sets the nearest and furthest distances drawn. The far plane is
+    /// kept beyond the near one, which is kept in front of the eye.
+    pub(crate) fn set_clip_planes(&mut self, near: f32, far: f32) {
+        self.lens.near = near.max(1e-4);
+        self.lens.far = far.max(2.0 * self.lens.near);
+        self.update_projection();
+    }
+
+    /// Keeps the projection matching a window `width` by `height`, so that
+    /// the image isn't stretched.
+    pub(crate) fn set_aspect_ratio(&mut self, width: f32, height: f32) {
+        if width > 0.0 && height > 0.0 {
+            self.aspect_ratio = width / height;
+            self.update_projection();
         }
     }
 
-    fn view_matrix(rot: Vec3, camera_pos: Vec3) -> Mat4 {
-        let x_rot = Mat4::from_rotation_x(rot.x);
-        let y_rot = Mat4::from_rotation_y(rot.y);
-        let z_rot = Mat4::from_rotation_z(rot.z);
+    /// Turns to look straight along `view`'s axis. Orbiting, the camera
+    /// swings round the pivot to get there.
+    pub(crate) fn set_view(&mut self, view: View) {
+        (self.yaw, self.pitch) = match view {
+            View::Top => (0.0, -std::f32::consts::FRAC_PI_2),
+            View::Side => (1.5 * std::f32::consts::PI, 0.0),
+            View::Front => (0.0, 0.0),
+        };
+    }
+
+    fn direction(yaw: f32, pitch: f32) -> Vec3 {
+        Vec3::new(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos())
+    }
 
-        let rot = z_rot * y_rot * x_rot;
-        let trans = Mat4::from_translation(camera_pos);
+    /// Square to `direction(yaw, pitch)`, even looking straight up or
+    /// down, where the world's up is no help.
+    fn up(yaw: f32, pitch: f32) -> Vec3 {
+        Self::direction(yaw, pitch + std::f32::consts::FRAC_PI_2)
+    }
 
-        rot * trans
+    fn view_matrix(camera_pos: Vec3, yaw: f32, pitch: f32) -> Mat4 {
+        Mat4::look_at_lh(camera_pos, camera_pos + Self::direction(yaw, pitch), Self::up(yaw, pitch))
     }
 
     pub(crate) fn model_view_proj(&self, model: Mat4) -> Mat4 {
         self.perspective_mat * self.view_mat * model
     }
 
-    pub(crate) fn add_movement(&mut self, movements: Movement) {
-        if movements.any_movement() {
-            let cam_dir = Vec3::new(
-                -self.rotation.x.cos() * self.rotation.y.sin(),
-                self.rotation.x.sin(),
-                self.rotation.x.cos() * self.rotation.y.sin()
-            ).normalize_or_zero();
-            let cam_dir = (self.looking_at - self.cam_position).normalize();
-            // let speed = delta_t.as_micros() as f32 / 100000.0 * 0.19;
-            let speed = 0.018;
-            
-            if movements.strafe_forward {
-                self.cam_dest += speed * cam_dir;
+    /// The way the camera is looking.
+    pub(crate) fn forward(&self) -> Vec3 {
+        Self::direction(self.yaw, self.pitch)
+    }
+
+    /// Level with the horizon and square to `forward`.
+    pub(crate) fn right(&self) -> Vec3 {
+        Vec3::new(self.yaw.cos(), 0.0, -self.yaw.sin())
+    }
+
+    /// Turns the camera by a mouse movement of `delta` pixels.
+    pub(crate) fn add_look(&mut self, delta: Vec2) {
+        self.turn(delta.x * MOUSE_SENSITIVITY, -delta.y * MOUSE_SENSITIVITY);
+    }
+
+    fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw).rem_euclid(std::f32::consts::TAU);
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub(crate) fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Switches between flying and orbiting without the view jumping:
+    /// an orbit starts around whatever's straight ahead at the current
+    /// orbit distance, and flying starts from wherever the orbit was.
+    pub(crate) fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode {
+            return;
+        }
+        self.mode = mode;
+        // following picks up from here too, gliding to the boid
+        self.cam_dest = self.cam_position;
+        self.looking_at = self.cam_position + self.forward() * self.distance;
+        self.pivot_dest = self.looking_at;
+        if mode == CameraMode::Orbit {
+            self.pan_offset = self.looking_at - self.target_point;
+        }
+    }
+
+    /// Moves the point being orbited to `point`, keeping any panning. The
+    /// camera glides after it rather than jumping.
+    pub(crate) fn set_target_point(&mut self, point: Vec3) {
+        self.target_point = point;
+        self.pivot_dest = point + self.pan_offset;
+    }
+
+    /// Tells a following camera where the boid is and which way it faces,
+    /// as the rotation taking +Z to its heading. The camera glides after it
+    /// in `update`.
+    pub(crate) fn set_follow_pose(&mut self, position: Vec3, rotation: Quat) {
+        match self.mode {
+            CameraMode::Chase => {
+                self.cam_dest = position + rotation * self.chase_offset;
+                self.pivot_dest = position;
+            }
+            CameraMode::FirstPerson => {
+                self.cam_dest = position + rotation * RIDE_OFFSET;
+                self.pivot_dest = self.cam_dest + rotation * Vec3::Z * RIDE_LOOK_AHEAD;
             }
-            if movements.strafe_back {
-                self.cam_dest -= speed * cam_dir;
+            CameraMode::Fly | CameraMode::Orbit => {}
+        }
+    }
+
+    /// Sets where a chasing camera sits relative to the boid: +Z is ahead
+    /// of it and +Y above it.
+    pub(crate) fn set_chase_offset(&mut self, offset: Vec3) {
+        self.chase_offset = offset;
+    }
+
+    /// Centres the orbit on the target again after panning.
+    pub(crate) fn reset_pan(&mut self) {
+        self.pan_offset = Vec3::ZERO;
+        self.pivot_dest = self.target_point;
+    }
+
+    /// Circles the pivot by a mouse drag of `delta` pixels. Dragging moves
+    /// the scene along with the mouse.
+    pub(crate) fn add_orbit(&mut self, delta: Vec2) {
+        self.add_look(delta);
+    }
+
+    /// Slides the pivot across the view by a mouse drag of `delta` pixels,
+    /// so that the scene follows the mouse.
+    pub(crate) fn add_pan(&mut self, delta: Vec2) {
+        let up = Self::up(self.yaw, self.pitch);
+        let scale = PAN_SENSITIVITY * self.distance;
+        self.pan_offset += (-self.right() * delta.x + up * delta.y) * scale;
+        self.pivot_dest = self.target_point + self.pan_offset;
+    }
+
+    /// Moves an orbiting camera in (or out, for negative `steps`) by a step
+    /// per notch of the scroll wheel. An orthographic view zooms in too.
+    pub(crate) fn add_zoom_steps(&mut self, steps: f32) {
+        self.distance = (self.distance * ZOOM_STEP.powf(steps)).clamp(MIN_DISTANCE, MAX_DISTANCE);
+        if self.lens.projection == Projection::Orthographic {
+            self.update_projection();
+        }
+    }
+
+    pub(crate) fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Speeds flying up (or down, for negative `steps`) by a step per
+    /// notch of the scroll wheel.
+    pub(crate) fn add_speed_steps(&mut self, steps: f32) {
+        self.speed = (self.speed * SPEED_STEP.powf(steps)).clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub(crate) fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Turns and moves the camera by however far `movements` take it in
+    /// `delta_t`. The position then catches up in `update`. Orbiting, the
+    /// turns circle the pivot and forward and back zoom instead; following
+    /// a boid, the boid does the steering.
+    pub(crate) fn add_movement(&mut self, movements: &Movement, delta_t: Duration) {
+        if matches!(self.mode, CameraMode::Chase | CameraMode::FirstPerson) {
+            return;
+        }
+        let dt = delta_t.as_secs_f32();
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+
+        let turn = TURN_RATE * dt;
+        self.turn(
+            turn * axis(movements.yaw_right, movements.yaw_left),
+            turn * axis(movements.pitch_up, movements.pitch_down),
+        );
+
+        if self.mode == CameraMode::Orbit {
+            let zoom = axis(movements.strafe_forward, movements.strafe_back);
+            self.add_zoom_steps(zoom * dt / SECONDS_PER_ZOOM_STEP);
+            return;
+        }
+
+        let mut speed = self.speed;
+        if movements.sprint {
+            speed *= SPRINT_FACTOR;
+        }
+        if movements.slow {
+            speed *= SLOW_FACTOR;
+        }
+        let dir = self.forward() * axis(movements.strafe_forward, movements.strafe_back)
+            + self.right() * axis(movements.strafe_right, movements.strafe_left)
+            + Vec3::Y * axis(movements.up, movements.down);
+        self.cam_dest += dir.normalize_or_zero() * speed * dt;
+    }
+
+    pub(crate) fn update(&mut self, delta_t: Duration) {
+        let s = (delta_t.as_secs_f32() / SMOOTHING).min(1.0);
+        match self.mode {
+            CameraMode::Fly => {
+                self.cam_position = self.cam_position.lerp(self.cam_dest, s);
+                self.view_mat = Self::view_matrix(self.cam_position, self.yaw, self.pitch);
             }
-            if movements.strafe_left {
-                self.cam_dest += cam_dir.cross(Vec3::new(0.0, 1.0, 0.0)).normalize() * speed;
+            CameraMode::Orbit => {
+                self.looking_at = self.looking_at.lerp(self.pivot_dest, s);
+                self.cam_position = self.looking_at - self.forward() * self.distance;
+                self.cam_dest = self.cam_position;
+                self.view_mat = Self::view_matrix(self.cam_position, self.yaw, self.pitch);
             }
-            if movements.strafe_right {
-                self.cam_dest -= cam_dir.cross(Vec3::new(0.0, 1.0, 0.0)).normalize() * speed;
+            CameraMode::Chase | CameraMode::FirstPerson => {
+                self.cam_position = self.cam_position.lerp(self.cam_dest, s);
+                self.looking_at = self.looking_at.lerp(self.pivot_dest, s);
+                // keeping yaw and pitch up to date lets flying or orbiting
+                // carry on from this view
+                if let Some(dir) = (self.looking_at - self.cam_position).try_normalize() {
+                    self.yaw = dir.x.atan2(dir.z).rem_euclid(std::f32::consts::TAU);
+                    self.pitch = dir.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+                }
+                self.view_mat = Self::view_matrix(self.cam_position, self.yaw, self.pitch);
             }
         }
     }
 
-    pub(crate) fn update(&mut self, delta_t: Duration) {
-        let s = delta_t.as_secs_f32() / 0.00833333;
-        self.cam_position = self.cam_position.lerp(self.cam_dest, s);
-        self.view_mat = Self::view_matrix(self.rotation, self.cam_position);
+    pub(crate) fn position(&self) -> Vec3 {
+        self.cam_position
+    }
+
+    /// The ray from the eye through `cursor`, in pixels from the top left
+    /// of a `size` window, as its origin and direction.
+    pub(crate) fn ray(&self, cursor: Vec2, size: Vec2) -> (Vec3, Vec3) {
+        let ndc = Vec2::new(2.0 * cursor.x / size.x - 1.0, 1.0 - 2.0 * cursor.y / size.y);
+        let inverse = (self.perspective_mat * self.view_mat).inverse();
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize_or_zero())
     }
 
     pub(crate) fn perspective_mat(&self) -> Mat4 {
@@ -104,30 +455,259 @@ impl Camera {
     }
 }
 
-impl Movement {
-    fn any_movement(&self) -> bool {
-        self.strafe_forward ||
-        self.strafe_back ||
-        self.strafe_left || 
-        self.strafe_right ||
-        self.pitch_up ||
-        self.pitch_down ||
-        self.yaw_left ||
-        self.yaw_right
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::{Quat, Vec2, Vec3};
+
+    use super::{Camera, CameraMode, Lens, Movement, Projection, View, MAX_PITCH, RIDE_OFFSET};
+
+    fn camera() -> Camera {
+        Camera::new(Vec3::new(0.0, 0.0, -1.0), Vec3::ZERO, 90.0, 0.01, 100.0, 1.0)
     }
-}
 
-impl Default for Movement {
-    fn default() -> Self {
-        Self {
-            strafe_forward: false,
-            strafe_back: false,
-            strafe_left: false,
-            strafe_right: false,
-            pitch_up: false,
-            pitch_down: false,
-            yaw_left: false,
-            yaw_right: false,
+    /// Runs `frames` frames of `dt` holding `movement`.
+    fn fly(camera: &mut Camera, movement: Movement, frames: u32, dt: Duration) {
+        for _ in 0..frames {
+            camera.add_movement(&movement, dt);
+            camera.update(dt);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn starts_looking_at_the_target() {
+        let camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 0.0, 1.0), 90.0, 0.01, 100.0, 1.0);
+        assert!(camera.forward().abs_diff_eq(Vec3::new(0.0, -1.0, -1.0).normalize(), 1e-5));
+        let target = camera.view_mat().transform_point3(Vec3::new(1.0, 0.0, 1.0));
+        assert!(target.x.abs() < 1e-5 && target.y.abs() < 1e-5 && target.z > 0.0);
+    }
+
+    #[test]
+    fn distance_flown_depends_on_time_not_frame_rate() {
+        let forward = Movement { strafe_forward: true, ..Movement::default() };
+        let mut slow_frames = camera();
+        fly(&mut slow_frames, forward, 30, Duration::from_secs_f32(1.0 / 30.0));
+        let mut fast_frames = camera();
+        fly(&mut fast_frames, forward, 120, Duration::from_secs_f32(1.0 / 120.0));
+
+        assert!(slow_frames.cam_dest.abs_diff_eq(fast_frames.cam_dest, 1e-4));
+        assert!(slow_frames.cam_dest.abs_diff_eq(Vec3::new(0.0, 0.0, -1.0 + slow_frames.speed()), 1e-4));
+    }
+
+    #[test]
+    fn modifiers_and_scrolling_change_the_speed() {
+        let dt = Duration::from_secs(1);
+        let travelled = |camera: &mut Camera, movement| {
+            let start = camera.cam_dest;
+            fly(camera, movement, 1, dt);
+            camera.cam_dest.distance(start)
+        };
+        let mut camera = camera();
+        let up = Movement { up: true, ..Movement::default() };
+        let base = travelled(&mut camera, up);
+        assert!((travelled(&mut camera, Movement { sprint: true, ..up }) - 4.0 * base).abs() < 1e-4);
+        assert!((travelled(&mut camera, Movement { slow: true, ..up }) - 0.25 * base).abs() < 1e-4);
+
+        camera.add_speed_steps(2.0);
+        assert!((travelled(&mut camera, up) - 1.44 * base).abs() < 1e-4);
+        camera.add_speed_steps(-1000.0);
+        assert!(camera.speed() > 0.0);
+    }
+
+    #[test]
+    fn looking_around_turns_and_clamps_pitch() {
+        let mut camera = camera();
+        camera.add_look(Vec2::new(std::f32::consts::FRAC_PI_2 / super::MOUSE_SENSITIVITY, 0.0));
+        assert!(camera.forward().abs_diff_eq(Vec3::X, 1e-5));
+        assert!(camera.right().abs_diff_eq(-Vec3::Z, 1e-5));
+
+        // moving the mouse up looks up, but never past vertical
+        camera.add_look(Vec2::new(0.0, -1e6));
+        assert!((camera.pitch - MAX_PITCH).abs() < 1e-6);
+        assert!(camera.forward().y < 1.0);
+
+        let mut camera = self::camera();
+        fly(&mut camera, Movement { yaw_left: true, ..Movement::default() }, 1, Duration::from_millis(100));
+        assert!(camera.forward().x < 0.0);
+    }
+
+    #[test]
+    fn strafing_is_level_and_to_the_side() {
+        let mut camera = camera();
+        camera.add_look(Vec2::new(0.0, -200.0));
+        fly(&mut camera, Movement { strafe_right: true, ..Movement::default() }, 1, Duration::from_secs(1));
+        let moved = camera.cam_dest - Vec3::new(0.0, 0.0, -1.0);
+        assert!(moved.y.abs() < 1e-6 && moved.z.abs() < 1e-6 && moved.x > 0.0);
+    }
+
+    #[test]
+    fn orbiting_circles_the_target_at_a_fixed_distance() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::Orbit);
+        camera.set_target_point(Vec3::new(1.0, 2.0, 3.0));
+        let frame = Duration::from_millis(16);
+        for _ in 0..100 {
+            camera.add_orbit(Vec2::new(7.0, 3.0));
+            camera.update(frame);
+            assert!((camera.position().distance(camera.looking_at) - 1.0).abs() < 1e-4);
+        }
+        assert!(camera.looking_at.abs_diff_eq(Vec3::new(1.0, 2.0, 3.0), 1e-4));
+
+        // the target is dead centre in view
+        let target = camera.view_mat().transform_point3(Vec3::new(1.0, 2.0, 3.0));
+        assert!(target.x.abs() < 1e-4 && target.y.abs() < 1e-4 && target.z > 0.0);
+
+        camera.add_zoom_steps(-3.0);
+        camera.update(frame);
+        assert!((camera.position().distance(camera.looking_at) - 0.9f32.powi(-3)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn switching_modes_keeps_the_view() {
+        let mut camera = camera();
+        let frame = Duration::from_millis(16);
+        camera.update(frame);
+        let fly_view = camera.view_mat();
+
+        camera.set_mode(CameraMode::Orbit);
+        camera.update(frame);
+        assert!(camera.view_mat().abs_diff_eq(fly_view, 1e-5));
+
+        camera.add_orbit(Vec2::new(300.0, 0.0));
+        camera.update(frame);
+        let orbit_view = camera.view_mat();
+        camera.set_mode(CameraMode::Fly);
+        camera.update(frame);
+        assert!(camera.view_mat().abs_diff_eq(orbit_view, 1e-5));
+    }
+
+    #[test]
+    fn panning_moves_the_pivot_across_the_view_until_reset() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::Orbit);
+        camera.set_target_point(Vec3::ZERO);
+        camera.reset_pan();
+        camera.add_pan(Vec2::new(100.0, 0.0));
+        camera.update(Duration::from_secs(1));
+
+        // dragging right slides the view left, so the scene follows the mouse
+        assert!(camera.looking_at.x < 0.0);
+        assert!(camera.looking_at.y.abs() < 1e-6 && camera.looking_at.z.abs() < 1e-6);
+
+        // the pan sticks as the target moves, until it's reset
+        camera.set_target_point(Vec3::Y);
+        camera.update(Duration::from_secs(1));
+        assert!(camera.looking_at.x < 0.0 && (camera.looking_at.y - 1.0).abs() < 1e-6);
+        camera.reset_pan();
+        camera.update(Duration::from_secs(1));
+        assert!(camera.looking_at.abs_diff_eq(Vec3::Y, 1e-6));
+    }
+
+    #[test]
+    fn chasing_settles_behind_the_boid_and_looks_at_it() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::Chase);
+        camera.set_chase_offset(Vec3::new(0.0, 0.5, -2.0));
+        // heading along +X
+        let rotation = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let boid = Vec3::new(3.0, 1.0, 0.0);
+        for _ in 0..100 {
+            camera.set_follow_pose(boid, rotation);
+            camera.update(Duration::from_millis(4));
+        }
+        assert!(camera.position().abs_diff_eq(Vec3::new(1.0, 1.5, 0.0), 1e-4));
+        let seen = camera.view_mat().transform_point3(boid);
+        assert!(seen.x.abs() < 1e-4 && seen.y.abs() < 1e-4 && seen.z > 0.0);
+
+        // steering the camera is left to the boid
+        let before = camera.position();
+        camera.add_movement(&Movement { strafe_forward: true, yaw_left: true, ..Movement::default() }, Duration::from_secs(1));
+        camera.update(Duration::from_millis(4));
+        assert!(camera.position().abs_diff_eq(before, 1e-6));
+    }
+
+    #[test]
+    fn riding_along_looks_where_the_boid_is_going() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::FirstPerson);
+        let rotation = Quat::from_rotation_y(-std::f32::consts::FRAC_PI_2);
+        for _ in 0..100 {
+            camera.set_follow_pose(Vec3::ZERO, rotation);
+            camera.update(Duration::from_millis(4));
+        }
+        assert!(camera.position().abs_diff_eq(RIDE_OFFSET, 1e-4));
+        assert!(camera.forward().abs_diff_eq(-Vec3::X, 1e-4));
+
+        // flying on carries on from the same view
+        let view = camera.view_mat();
+        camera.set_mode(CameraMode::Fly);
+        camera.update(Duration::from_millis(4));
+        assert!(camera.view_mat().abs_diff_eq(view, 1e-5));
+    }
+
+    #[test]
+    fn the_centre_of_the_window_picks_straight_ahead() {
+        let camera = camera();
+        let (origin, dir) = camera.ray(Vec2::new(400.0, 300.0), Vec2::new(800.0, 600.0));
+        assert!(dir.abs_diff_eq(camera.forward(), 1e-5));
+        assert!((origin - camera.position()).cross(dir).length() < 1e-4);
+        let (_, right) = camera.ray(Vec2::new(800.0, 300.0), Vec2::new(800.0, 600.0));
+        assert!(right.dot(camera.right()) > 0.0);
+    }
+
+    #[test]
+    fn the_projection_follows_the_window_and_the_lens() {
+        let mut camera = camera();
+        let corner = Vec3::new(1.0, 1.0, 0.0);
+        let seen = |camera: &Camera| camera.perspective_mat().project_point3(camera.view_mat().transform_point3(corner));
+        // a 90 degree square view puts the corner of a unit cube one unit
+        // away right in the corner of the window
+        assert!(seen(&camera).truncate().abs_diff_eq(Vec2::ONE, 1e-5));
+
+        camera.set_aspect_ratio(1600.0, 800.0);
+        assert!(seen(&camera).truncate().abs_diff_eq(Vec2::new(0.5, 1.0), 1e-5));
+        camera.set_aspect_ratio(0.0, 800.0);
+        assert!(seen(&camera).truncate().abs_diff_eq(Vec2::new(0.5, 1.0), 1e-5));
+
+        camera.set_fov(1000.0);
+        assert_eq!(camera.lens().fov, 150.0);
+        camera.set_clip_planes(-1.0, 0.0);
+        let lens = camera.lens();
+        assert!(lens.near > 0.0 && lens.far > lens.near);
+
+        camera.set_lens(Lens { projection: Projection::Perspective, fov: 90.0, near: 0.01, far: 1.5 });
+        assert!(seen(&camera).z < 1.0);
+        camera.set_clip_planes(0.01, 0.9);
+        assert!(seen(&camera).z > 1.0);
+    }
+
+    #[test]
+    fn orthographic_views_look_along_the_axes() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::Orbit);
+        camera.set_projection(Projection::Orthographic);
+        camera.set_view(View::Top);
+        camera.update(Duration::from_secs(1));
+        assert!(camera.position().abs_diff_eq(Vec3::Y, 1e-5));
+        let view_proj = camera.perspective_mat() * camera.view_mat();
+
+        // +Z is up the screen, and points the same distance off the pivot
+        // land the same distance from the centre whatever their depth
+        let up = view_proj.project_point3(Vec3::new(0.0, -5.0, 0.5));
+        assert!(up.truncate().abs_diff_eq(Vec2::new(0.0, 0.5), 1e-5));
+        let near = view_proj.project_point3(Vec3::new(0.5, 0.5, 0.0));
+        assert!(near.truncate().abs_diff_eq(Vec2::new(0.5, 0.0), 1e-5));
+        assert!((0.0..=1.0).contains(&up.z) && (0.0..=1.0).contains(&near.z));
+
+        // zooming shrinks the view
+        camera.add_zoom_steps(1.0);
+        let zoomed = (camera.perspective_mat() * camera.view_mat()).project_point3(Vec3::new(0.5, 0.5, 0.0));
+        assert!((zoomed.x - 0.5 / 0.9).abs() < 1e-5);
+
+        camera.set_view(View::Side);
+        camera.update(Duration::from_secs(1));
+        assert!(camera.position().abs_diff_eq(0.9 * Vec3::X, 1e-5));
+        assert!(camera.forward().abs_diff_eq(-Vec3::X, 1e-5));
+    }
+}
@@ -0,0 +1,499 @@
+use std::{error::Error, ffi::OsString, fs, path::PathBuf, rc::Rc, time::Duration};
+
+use clap::{CommandFactory, Parser};
+use glam::Vec3;
+
+use crate::{
+    coloring::{ColorBy, Coloring, Colormap},
+    input::Bindings,
+    offscreen::OffscreenRenderer,
+    recording::Recorder,
+    scenario::{Flock, Scenario, Spawn, Velocity},
+    timestep::{FixedTimestep, DEFAULT_TICK_RATE},
+    world::MAX_CELLS_PER_SIDE,
+    Backend, CameraMode, Config, Vsync, World,
+};
+
+/// Ticks a headless run takes unless `--ticks` says otherwise.
+const DEFAULT_HEADLESS_TICKS: u64 = 600;
+
+/// Boids flocking simulation.
+#[derive(Parser, Debug, Clone)]
+#[clap(name = "iridium", version, about)]
+pub struct Args {
+    /// Scenario file describing the world and its initial flocks (RON)
+    #[clap(short, long, value_name = "PATH")]
+    pub scenario: Option<PathBuf>,
+
+    /// Seed for the simulation's random number generator, overriding the scenario's
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Spawn this many boids spread uniformly through the world instead of the scenario's flocks
+    #[clap(short = 'n', long, value_name = "COUNT")]
+    pub boids: Option<usize>,
+
+    /// Side length of the cubic world
+    #[clap(long, value_name = "LEN", value_parser = positive_f32)]
+    pub world_size: Option<f32>,
+
+    /// Number of spatial hash cells along each side of the world
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=MAX_CELLS_PER_SIDE as i64))]
+    pub cells_per_side: Option<u32>,
+
+    /// Window width in pixels
+    #[clap(long, value_name = "PX", default_value_t = 1280, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    /// Window height in pixels
+    #[clap(long, value_name = "PX", default_value_t = 720, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: u32,
+
+    /// Start in borderless fullscreen
+    #[clap(long)]
+    pub fullscreen: bool,
+
+    /// Graphics API to render with; `auto` honors the WGPU_BACKEND environment variable
+    #[clap(long, arg_enum, value_name = "API", default_value = "auto")]
+    pub backend: Backend,
+
+    /// Frame pacing
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "off")]
+    pub vsync: Vsync,
+
+    /// Simulation ticks per second
+    #[clap(long, value_name = "HZ", default_value_t = DEFAULT_TICK_RATE, value_parser = positive_f64)]
+    pub tick_rate: f64,
+
+    /// Run the simulation without a window or GPU
+    #[clap(long)]
+    pub headless: bool,
+
+    /// Number of ticks to run in headless mode [default: 600]
+    #[clap(long, value_name = "N")]
+    pub ticks: Option<u64>,
+
+    /// Resume from a snapshot written by --save instead of building a scenario
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["scenario", "seed", "boids", "world-size", "cells-per-side"])]
+    pub load: Option<PathBuf>,
+
+    /// Write a snapshot of the world here when the run ends (.ron for text, anything else for binary)
+    #[clap(long, value_name = "PATH")]
+    pub save: Option<PathBuf>,
+
+    /// Write the headless run's report here as well as to stdout
+    #[clap(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Render the final state of a headless run to this PNG, at --width x --height
+    #[clap(long, value_name = "PATH")]
+    pub screenshot: Option<PathBuf>,
+
+    /// Record a headless run as numbered PNG frames plus a manifest in this directory
+    #[clap(long, value_name = "DIR")]
+    pub record: Option<PathBuf>,
+
+    /// Record only every Nth tick
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub record_every: u64,
+
+    /// Draw boids with this model (.obj, .gltf or .glb), facing +Z with +Y up
+    #[clap(long, value_name = "PATH")]
+    pub mesh: Option<PathBuf>,
+
+    /// What boids' colors show
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "mesh")]
+    pub color_by: ColorChoice,
+
+    /// Colormap for the speed, density, acceleration and altitude color modes
+    #[clap(long, arg_enum, value_name = "NAME", default_value = "viridis")]
+    pub colormap: Colormap,
+
+    /// How the camera moves at startup
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "fly")]
+    pub camera: CameraMode,
+
+    /// Index of the boid to orbit, chase or ride along on; clicking a boid selects it too
+    #[clap(long, value_name = "ID")]
+    pub follow: Option<usize>,
+
+    /// Where the chase camera sits relative to the boid, as right,up,ahead
+    #[clap(long, value_name = "X,Y,Z", value_parser = vec3, allow_hyphen_values = true)]
+    pub chase_offset: Option<Vec3>,
+
+    /// Key bindings file (RON); see bindings/default.ron
+    #[clap(long, value_name = "PATH")]
+    pub bindings: Option<PathBuf>,
+
+    /// Directory screenshots taken with F12 are written to
+    #[clap(long, value_name = "DIR", default_value = ".")]
+    pub screenshot_dir: PathBuf,
+}
+
+/// The color modes that can be picked on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum ColorChoice {
+    /// The mesh's own colors
+    Mesh,
+    Speed,
+    /// Number of boids within perception range
+    Density,
+    /// The scenario flock each boid was spawned in
+    Group,
+    Heading,
+    /// Magnitude of the steering force
+    Acceleration,
+    /// Height above the world's center
+    Altitude,
+}
+
+impl Args {
+    /// Parses `iridium-headless`'s command line, which takes the same
+    /// options as `iridium` but always runs headless.
+    pub fn try_parse_headless_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut args = Self::try_parse_from(args)?;
+        args.headless = true;
+        args.check_modes()?;
+        Ok(args)
+    }
+
+    /// Rejects options that only make sense in the other mode. Checked
+    /// after parsing rather than by clap, since the headless binary turns
+    /// on `headless` itself.
+    pub fn check_modes(&self) -> Result<(), clap::Error> {
+        if self.headless {
+            return Ok(());
+        }
+        let headless_only = [
+            ("--ticks", self.ticks.is_some()),
+            ("--report", self.report.is_some()),
+            ("--screenshot", self.screenshot.is_some()),
+            ("--record", self.record.is_some()),
+        ];
+        match headless_only.into_iter().find(|&(_, given)| given) {
+            Some((arg, _)) => {
+                Err(Self::command().error(clap::ErrorKind::ArgumentConflict, format!("{} needs --headless", arg)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// How many ticks a headless run takes.
+    pub fn ticks(&self) -> u64 {
+        self.ticks.unwrap_or(DEFAULT_HEADLESS_TICKS)
+    }
+
+    /// The scenario to run: the one given with `--scenario`, or the default
+    /// one, with every override from the command line applied and the
+    /// result checked.
+    pub fn scenario(&self) -> Result<Scenario, Box<dyn Error>> {
+        let mut scenario = match &self.scenario {
+            Some(path) => Scenario::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            None => Scenario::default(),
+        };
+        if let Some(seed) = self.seed {
+            scenario.seed = Some(seed);
+        }
+        if let Some(side_len) = self.world_size {
+            scenario.side_len = side_len;
+        }
+        if let Some(cells) = self.cells_per_side {
+            scenario.cells_per_side = cells as usize;
+        }
+        if let Some(count) = self.boids {
+            let half = 0.4 * scenario.side_len;
+            scenario.flocks = vec![Flock {
+                count,
+                spawn: Spawn::UniformBox { min: Vec3::splat(-half), max: Vec3::splat(half) },
+                velocity: Velocity::default(),
+            }];
+        }
+        scenario.validate().map_err(|e| match &self.scenario {
+            Some(path) => format!("{}: {}", path.display(), e),
+            None => e.to_string(),
+        })?;
+        Ok(scenario)
+    }
+
+    /// The world to run: the snapshot given with `--load`, or else a new
+    /// one built from the scenario.
+    pub fn world(&self) -> Result<World, Box<dyn Error>> {
+        match &self.load {
+            Some(path) => Ok(World::load(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+            None => Ok(self.scenario()?.build()?),
+        }
+    }
+
+    /// The windowed app's settings, reading the key bindings file if one
+    /// was given.
+    pub fn config(&self) -> Result<Config, Box<dyn Error>> {
+        let bindings = match &self.bindings {
+            Some(path) => Bindings::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            None => Bindings::default(),
+        };
+        Ok(Config {
+            backend: self.backend,
+            vsync: self.vsync,
+            tick_rate: self.tick_rate,
+            screenshot_dir: self.screenshot_dir.clone(),
+            mesh: self.mesh.clone(),
+            coloring: self.coloring(),
+            bindings,
+            camera_mode: self.camera,
+            follow: self.follow,
+            chase_offset: self.chase_offset.unwrap_or(Config::default().chase_offset),
+            ..Config::default()
+        })
+    }
+
+    pub fn coloring(&self) -> Coloring {
+        let by = match self.color_by {
+            ColorChoice::Mesh => ColorBy::Mesh,
+            ColorChoice::Speed => ColorBy::Speed,
+            ColorChoice::Density => ColorBy::Density,
+            ColorChoice::Group => ColorBy::Group,
+            ColorChoice::Heading => ColorBy::Heading,
+            ColorChoice::Acceleration => ColorBy::Attribute(Rc::new(|world, i| world.boids()[i].acceleration().length())),
+            ColorChoice::Altitude => ColorBy::Attribute(Rc::new(|world, i| world.boids()[i].position().y)),
+        };
+        Coloring::new(by, self.colormap)
+    }
+
+    /// The length of one tick, the same as the windowed app steps by.
+    pub fn timestep(&self) -> Duration {
+        FixedTimestep::from_hz(self.tick_rate).step()
+    }
+}
+
+/// Runs `--ticks` ticks of the scenario without a window, printing the
+/// report and writing whatever outputs were asked for.
+pub fn run_headless(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut world = args.world()?;
+    // set up before running, so a missing adapter or a bad mesh doesn't
+    // only show up once every tick has been simulated
+    let mut screenshot_renderer = match &args.screenshot {
+        Some(_) => Some(offscreen_renderer(args)?),
+        None => None,
+    };
+    let mut recorder = match &args.record {
+        Some(dir) => {
+            let mut renderer = offscreen_renderer(args)?;
+            renderer.frame(&world);
+            let mut recorder = Recorder::new(dir, args.record_every, args.tick_rate, renderer, &world)?;
+            recorder.record(&world)?;
+            Some(recorder)
+        }
+        None => None,
+    };
+
+    let report = crate::headless::run(&mut world, args.ticks(), args.timestep(), |tick, world| {
+        if tick % 100 == 0 {
+            log::info!("tick {}: polarization {:.3}", tick, world.stats().polarization);
+        }
+        match recorder.as_mut() {
+            Some(recorder) => recorder.record(world),
+            None => Ok(()),
+        }
+    });
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            // still describe the frames that did get written
+            if let Some(Err(e)) = recorder.map(Recorder::finish) {
+                log::warn!("{}", e);
+            }
+            return Err(e.into());
+        }
+    };
+    if let Some(recorder) = recorder {
+        let frames = recorder.frames();
+        let manifest = recorder.finish()?;
+        log::info!("recorded {} frames, manifest at {}", frames, manifest.display());
+    }
+
+    let report = format!("{}\nstate hash:   {:016x}", report, world.state_hash());
+    println!("{}", report);
+    if let Some(path) = &args.report {
+        fs::write(path, report + "\n").map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.save {
+        world.save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    if let (Some(path), Some(renderer)) = (&args.screenshot, screenshot_renderer.as_mut()) {
+        renderer.frame(&world);
+        renderer.render(&world).save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn offscreen_renderer(args: &Args) -> Result<OffscreenRenderer, Box<dyn Error>> {
+    let mut renderer = pollster::block_on(OffscreenRenderer::new(args.width, args.height, args.backend))?;
+    if let Some(path) = &args.mesh {
+        renderer.load_mesh(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    renderer.set_coloring(args.coloring());
+    Ok(renderer)
+}
+
+fn positive_f32(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        Ok(_) => Err("must be a positive number".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn vec3(s: &str) -> Result<Vec3, String> {
+    let parts = s.split(',').map(|part| part.trim().parse::<f32>()).collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    match parts[..] {
+        [x, y, z] if parts.iter().all(|v| v.is_finite()) => Ok(Vec3::new(x, y, z)),
+        _ => Err("must be three comma-separated numbers".into()),
+    }
+}
+
+fn positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        Ok(_) => Err("must be a positive number".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use glam::Vec3;
+
+    use super::Args;
+    use crate::{CameraMode, Vsync};
+
+    #[test]
+    fn the_cli_definition_is_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn overrides_are_applied_to_the_scenario() {
+        let args = Args::try_parse_from([
+            "iridium", "--seed", "3", "--boids", "25", "--world-size", "8", "--cells-per-side", "4", "--vsync", "mailbox",
+        ]).unwrap();
+        let scenario = args.scenario().unwrap();
+
+        assert_eq!(args.vsync, Vsync::Mailbox);
+        assert_eq!(scenario.seed, Some(3));
+        assert_eq!(scenario.side_len, 8.0);
+        assert_eq!(scenario.cells_per_side, 4);
+        assert_eq!(scenario.build().unwrap().boids().len(), 25);
+    }
+
+    #[test]
+    fn scenarios_are_checked_after_overrides() {
+        let path = std::env::temp_dir().join(format!("iridium-cli-scenario-{}.ron", std::process::id()));
+        std::fs::write(&path, "(cells_per_side: 0, flocks: [])").unwrap();
+        let scenario = path.to_str().unwrap();
+
+        let error = Args::try_parse_from(["iridium", "--scenario", scenario]).unwrap().scenario().unwrap_err();
+        assert!(error.to_string().contains("cells per side"), "{}", error);
+        let fixed = Args::try_parse_from(["iridium", "--scenario", scenario, "--cells-per-side", "4"]).unwrap();
+        assert_eq!(fixed.scenario().unwrap().cells_per_side, 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn saved_runs_can_be_resumed() {
+        let path = std::env::temp_dir().join(format!("iridium-cli-resume-{}.bin", std::process::id()));
+        let mut world = Args::try_parse_from(["iridium", "--seed", "9", "--boids", "40"]).unwrap().world().unwrap();
+        world.update(std::time::Duration::from_millis(16));
+        world.save(&path).unwrap();
+
+        let resume = Args::try_parse_from(["iridium", "--load", path.to_str().unwrap()]).unwrap();
+        assert_eq!(resume.world().unwrap().state_hash(), world.state_hash());
+        // a snapshot already fixes everything a scenario would
+        assert!(Args::try_parse_from(["iridium", "--load", "x.bin", "--boids", "3"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--load", "x.bin", "--world-size", "3"]).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(resume.world().is_err());
+    }
+
+    #[test]
+    fn the_headless_binary_can_record_without_asking_for_headless() {
+        let args = Args::try_parse_headless_from(["iridium-headless", "--record", "dir"]).unwrap();
+        assert!(args.headless);
+        assert_eq!(args.record.as_deref(), Some(std::path::Path::new("dir")));
+        assert!(Args::try_parse_from(["iridium", "--headless", "--record", "dir"]).unwrap().check_modes().is_ok());
+    }
+
+    #[test]
+    fn recording_stops_at_the_first_frame_that_cannot_be_written() {
+        use crate::{offscreen::OffscreenRenderer, renderer::RendererError, Backend};
+
+        if let Err(e @ RendererError::NoAdapter { .. }) = pollster::block_on(OffscreenRenderer::new(1, 1, Backend::Auto)) {
+            eprintln!("skipping: {}", e);
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("iridium-cli-record-{}", std::process::id()));
+        let record = |dir: &std::path::Path| {
+            let args = Args::try_parse_headless_from([
+                "iridium-headless", "--record", dir.to_str().unwrap(), "--ticks", "10", "--boids", "5", "--width", "16",
+                "--height", "16",
+            ]).unwrap();
+            super::run_headless(&args)
+        };
+
+        // somewhere no directory can be made
+        std::fs::write(&dir, "").unwrap();
+        assert!(record(&dir.join("frames")).is_err());
+        std::fs::remove_file(&dir).unwrap();
+
+        // frame 0 goes in before the first tick, frame 1 can't be written
+        std::fs::create_dir_all(dir.join("frame_000001.png")).unwrap();
+        assert!(record(&dir).is_err());
+        assert!(dir.join("frame_000000.png").is_file());
+        assert!(!dir.join("frame_000002.png").exists());
+        let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        assert!(manifest.contains("frame_000000.png") && !manifest.contains("frame_000001.png"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn headless_only_options_need_headless() {
+        for args in [["--ticks", "10"], ["--report", "report.txt"], ["--screenshot", "end.png"], ["--record", "frames"]] {
+            let windowed = Args::try_parse_from(["iridium"].into_iter().chain(args)).unwrap();
+            assert!(windowed.check_modes().is_err(), "{:?}", args);
+            let headless = Args::try_parse_from(["iridium", "--headless"].into_iter().chain(args)).unwrap();
+            assert!(headless.check_modes().is_ok(), "{:?}", args);
+        }
+        assert_eq!(Args::try_parse_from(["iridium", "--headless"]).unwrap().ticks(), 600);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert!(Args::try_parse_from(["iridium", "--world-size", "0"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--tick-rate", "-5"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--cells-per-side", "0"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--width", "0"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--vsync", "sometimes"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--record", "frames"]).unwrap().check_modes().is_err());
+        assert!(Args::try_parse_from(["iridium", "--headless", "--record", "frames", "--record-every", "0"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--chase-offset", "0,1"]).is_err());
+        assert!(Args::try_parse_from(["iridium", "--chase-offset", "0,1,inf"]).is_err());
+    }
+
+    #[test]
+    fn the_camera_can_start_chasing_a_boid() {
+        let args = Args::try_parse_from(["iridium", "--camera", "chase", "--follow", "7", "--chase-offset", "0, 0.5,-2"]).unwrap();
+        let config = args.config().unwrap();
+        assert_eq!(config.camera_mode, CameraMode::Chase);
+        assert_eq!(config.follow, Some(7));
+        assert_eq!(config.chase_offset, Vec3::new(0.0, 0.5, -2.0));
+    }
+}
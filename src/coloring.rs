@@ -0,0 +1,229 @@
+use std::{fmt, rc::Rc};
+
+use glam::{const_vec4, Vec3, Vec4};
+
+use crate::world::World;
+
+/// An instance tint that leaves the mesh's own colors alone. A tint's RGB
+/// replaces the mesh color in proportion to its alpha.
+pub(crate) const NO_TINT: Vec4 = const_vec4!([1.0, 1.0, 1.0, 0.0]);
+
+/// Tableau 10, for telling flocks apart.
+const GROUP_PALETTE: [[f32; 3]; 10] = [
+    [0.122, 0.467, 0.706],
+    [1.000, 0.498, 0.055],
+    [0.173, 0.627, 0.173],
+    [0.839, 0.153, 0.157],
+    [0.580, 0.404, 0.741],
+    [0.549, 0.337, 0.294],
+    [0.890, 0.467, 0.761],
+    [0.498, 0.498, 0.498],
+    [0.737, 0.741, 0.133],
+    [0.090, 0.745, 0.812],
+];
+
+/// A per-boid value, given the world and the boid's index.
+pub type Attribute = dyn Fn(&World, usize) -> f32;
+
+/// How each boid is colored.
+#[derive(Clone, Debug, Default)]
+pub struct Coloring {
+    pub by: ColorBy,
+    /// Maps scalar attributes to colors; unused by the other modes.
+    pub colormap: Colormap,
+    /// The values mapped to either end of the colormap. Without one, the
+    /// smallest and largest values in the current frame are used.
+    pub range: Option<(f32, f32)>,
+}
+
+/// What a boid's color shows.
+#[derive(Clone, Default)]
+pub enum ColorBy {
+    /// Nothing: the mesh is drawn in its own colors.
+    #[default]
+    Mesh,
+    Speed,
+    /// How many other boids are within the perception radius.
+    Density,
+    /// Which flock of the scenario the boid was spawned in.
+    Group,
+    /// The direction of travel, as `0.5 + 0.5 * direction` in RGB.
+    Heading,
+    /// Any per-boid value, e.g. `|world, i| world.boids()[i].position().y`.
+    Attribute(Rc<Attribute>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Turbo,
+    Grayscale,
+}
+
+impl fmt::Debug for ColorBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorBy::Mesh => write!(f, "Mesh"),
+            ColorBy::Speed => write!(f, "Speed"),
+            ColorBy::Density => write!(f, "Density"),
+            ColorBy::Group => write!(f, "Group"),
+            ColorBy::Heading => write!(f, "Heading"),
+            ColorBy::Attribute(_) => write!(f, "Attribute(..)"),
+        }
+    }
+}
+
+impl Coloring {
+    pub fn new(by: ColorBy, colormap: Colormap) -> Self {
+        Self { by, colormap, range: None }
+    }
+
+    /// Fills `out` with one instance tint per boid.
+    pub(crate) fn tints(&self, world: &World, out: &mut Vec<Vec4>) {
+        out.clear();
+        let boids = world.boids();
+        match &self.by {
+            ColorBy::Mesh => out.resize(boids.len(), NO_TINT),
+            ColorBy::Group => out.extend(boids.iter().map(|b| {
+                Vec3::from(GROUP_PALETTE[b.group() as usize % GROUP_PALETTE.len()]).extend(1.0)
+            })),
+            ColorBy::Heading => out.extend(boids.iter().map(|b| match b.velocity().try_normalize() {
+                Some(heading) => (0.5 + 0.5 * heading).extend(1.0),
+                None => NO_TINT,
+            })),
+            ColorBy::Speed => self.map_scalars(boids.iter().map(|b| b.velocity().length()), out),
+            ColorBy::Density => {
+                let counts = world.neighbor_counts(world.steering().perception_radius());
+                self.map_scalars(counts.into_iter().map(|n| n as f32), out)
+            }
+            ColorBy::Attribute(attribute) => self.map_scalars((0..boids.len()).map(|i| attribute(world, i)), out),
+        }
+    }
+
+    fn map_scalars(&self, values: impl Iterator<Item = f32>, out: &mut Vec<Vec4>) {
+        let values: Vec<f32> = values.collect();
+        let (lo, hi) = self.range.unwrap_or_else(|| {
+            values.iter().fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+        });
+        let span = hi - lo;
+        out.extend(values.into_iter().map(|v| {
+            let t = if span > 0.0 { (v - lo) / span } else { 0.0 };
+            self.colormap.sample(t).extend(1.0)
+        }));
+    }
+}
+
+impl Colormap {
+    /// The color at `t`, clamped to [0, 1].
+    pub fn sample(&self, t: f32) -> Vec3 {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        match self {
+            // polynomial fit to matplotlib's viridis
+            Colormap::Viridis => {
+                let c = [
+                    Vec3::new(0.277_727_33, 0.005_407_344_5, 0.334_099_8),
+                    Vec3::new(0.105_093_04, 1.404_613_5, 1.384_590_2),
+                    Vec3::new(-0.330_861_83, 0.214_847_56, 0.095_095_16),
+                    Vec3::new(-4.634_230_6, -5.799_101, -19.332_441),
+                    Vec3::new(6.228_27, 14.179_933, 56.690_55),
+                    Vec3::new(4.776_385, -13.745_146, -65.353_035),
+                    Vec3::new(-5.435_456, 4.645_852_6, 26.312_435),
+                ];
+                c.iter().rev().fold(Vec3::ZERO, |acc, &c| acc * t + c)
+            }
+            // Google's polynomial approximation of turbo
+            Colormap::Turbo => {
+                let (t2, t3) = (t * t, t * t * t);
+                let (t4, t5) = (t2 * t2, t2 * t3);
+                let v4 = Vec4::new(1.0, t, t2, t3);
+                Vec3::new(
+                    v4.dot(Vec4::new(0.135_721_38, 4.615_392_6, -42.660_324, 132.131_08)) - 152.942_4 * t4 + 59.286_38 * t5,
+                    v4.dot(Vec4::new(0.091_402_61, 2.194_188_4, 4.842_966_6, -14.185_033)) + 4.277_298_5 * t4 + 2.829_566 * t5,
+                    v4.dot(Vec4::new(0.106_673_3, 12.641_946, -60.582_047, 110.362_77)) - 89.903_11 * t4 + 27.348_25 * t5,
+                )
+            }
+            Colormap::Grayscale => Vec3::splat(t),
+        }
+        .clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec3, Vec4};
+
+    use super::*;
+
+    #[test]
+    fn colormaps_match_their_endpoints() {
+        let viridis = Colormap::Viridis;
+        assert!(viridis.sample(0.0).abs_diff_eq(Vec3::new(0.267, 0.005, 0.329), 0.02));
+        assert!(viridis.sample(1.0).abs_diff_eq(Vec3::new(0.993, 0.906, 0.144), 0.02));
+        // the turbo fit is looser, especially at the ends
+        let turbo = Colormap::Turbo;
+        assert!(turbo.sample(0.0).abs_diff_eq(Vec3::new(0.190, 0.072, 0.232), 0.15));
+        assert!(turbo.sample(0.5).abs_diff_eq(Vec3::new(0.643, 0.990, 0.234), 0.15));
+        assert!(turbo.sample(1.0).abs_diff_eq(Vec3::new(0.480, 0.016, 0.011), 0.15));
+        // out of range values and NaNs are clamped rather than extrapolated
+        assert_eq!(viridis.sample(-3.0), viridis.sample(0.0));
+        assert_eq!(turbo.sample(7.0), turbo.sample(1.0));
+        assert_eq!(Colormap::Grayscale.sample(f32::NAN), Vec3::ZERO);
+    }
+
+    fn three_boids() -> World {
+        let mut world = World::new(10.0, 4);
+        world.add_boid_to_group(Vec3::ZERO, Vec3::new(0.1, 0.0, 0.0), 0);
+        world.add_boid_to_group(Vec3::new(0.2, 0.0, 0.0), Vec3::new(0.0, 0.5, 0.0), 1);
+        world.add_boid_to_group(Vec3::new(4.0, 4.0, 4.0), Vec3::new(0.0, 0.0, -0.9), 1);
+        world
+    }
+
+    fn tints(world: &World, coloring: Coloring) -> Vec<Vec4> {
+        let mut out = vec![Vec4::ONE; 7];
+        coloring.tints(world, &mut out);
+        assert_eq!(out.len(), world.boids().len());
+        out
+    }
+
+    #[test]
+    fn scalars_span_the_colormap() {
+        let world = three_boids();
+        let grayscale = tints(&world, Coloring::new(ColorBy::Speed, Colormap::Grayscale));
+        assert!(grayscale[0].abs_diff_eq(Vec4::new(0.0, 0.0, 0.0, 1.0), 1e-6));
+        assert!(grayscale[1].abs_diff_eq(Vec4::new(0.5, 0.5, 0.5, 1.0), 1e-6));
+        assert!(grayscale[2].abs_diff_eq(Vec4::ONE, 1e-6));
+
+        let fixed = Coloring { range: Some((0.0, 2.0)), ..Coloring::new(ColorBy::Speed, Colormap::Grayscale) };
+        assert!(tints(&world, fixed)[2].abs_diff_eq(Vec4::new(0.45, 0.45, 0.45, 1.0), 1e-6));
+
+        let height = ColorBy::Attribute(Rc::new(|world: &World, i| world.boids()[i].position().y));
+        let by_height = tints(&world, Coloring::new(height, Colormap::Grayscale));
+        assert_eq!(by_height[0], by_height[1]);
+        assert_eq!(by_height[2], Vec4::ONE);
+    }
+
+    #[test]
+    fn density_counts_other_boids_in_range() {
+        let world = three_boids();
+        assert_eq!(world.neighbor_counts(world.steering().perception_radius()), [1, 1, 0]);
+        let density = tints(&world, Coloring::new(ColorBy::Density, Colormap::Grayscale));
+        assert_eq!(density[0], Vec4::ONE);
+        assert_eq!(density[2], Vec4::new(0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn categorical_modes() {
+        let world = three_boids();
+        let mesh = tints(&world, Coloring::default());
+        assert!(mesh.iter().all(|&t| t == NO_TINT));
+
+        let groups = tints(&world, Coloring::new(ColorBy::Group, Colormap::default()));
+        assert_ne!(groups[0], groups[1]);
+        assert_eq!(groups[1], groups[2]);
+
+        let heading = tints(&world, Coloring::new(ColorBy::Heading, Colormap::default()));
+        assert_eq!(heading[0], Vec4::new(1.0, 0.5, 0.5, 1.0));
+        assert!(heading[2].abs_diff_eq(Vec4::new(0.5, 0.5, 0.0, 1.0), 1e-6));
+    }
+}
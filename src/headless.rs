@@ -0,0 +1,115 @@
+use std::{fmt, time::{Duration, Instant}};
+
+use crate::world::{FlockStats, World};
+
+/// Outcome of stepping a world without a window.
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    pub ticks: u64,
+    pub sim_time: Duration,
+    pub wall_time: Duration,
+    pub stats: FlockStats,
+}
+
+/// Steps `world` `ticks` times with a fixed `timestep`, calling `on_tick`
+/// after every tick with the number of ticks done so far. Stops early with
+/// the first error `on_tick` returns.
+pub fn run<E>(
+    world: &mut World,
+    ticks: u64,
+    timestep: Duration,
+    mut on_tick: impl FnMut(u64, &World) -> Result<(), E>,
+) -> Result<Report, E> {
+    let start = Instant::now();
+    for tick in 1..=ticks {
+        world.update(timestep);
+        on_tick(tick, world)?;
+    }
+
+    Ok(Report {
+        ticks,
+        sim_time: sim_time(timestep, ticks),
+        wall_time: start.elapsed(),
+        stats: world.stats(),
+    })
+}
+
+/// `ticks` steps of `timestep`, exactly, saturating rather than wrapping
+/// or panicking if that's more than a `Duration` holds.
+fn sim_time(timestep: Duration, ticks: u64) -> Duration {
+    const NANOS_PER_SEC: u128 = 1_000_000_000;
+    let nanos = timestep.as_nanos().checked_mul(ticks as u128);
+    match nanos.map(|n| (u64::try_from(n / NANOS_PER_SEC), (n % NANOS_PER_SEC) as u32)) {
+        Some((Ok(secs), subsec_nanos)) => Duration::new(secs, subsec_nanos),
+        _ => Duration::MAX,
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per_tick = self.wall_time.as_secs_f64() / self.ticks.max(1) as f64;
+        writeln!(f, "ticks:        {}", self.ticks)?;
+        writeln!(f, "sim time:     {:.3}s", self.sim_time.as_secs_f64())?;
+        writeln!(f, "wall time:    {:.3}s ({:.3}ms/tick)", self.wall_time.as_secs_f64(), per_tick * 1000.0)?;
+        write!(f, "{}", self.stats)
+    }
+}
+
+impl fmt::Display for FlockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "boids:        {}", self.boids)?;
+        writeln!(f, "centroid:     ({:.3}, {:.3}, {:.3})", self.centroid.x, self.centroid.y, self.centroid.z)?;
+        writeln!(f, "mean speed:   {:.3}", self.mean_speed)?;
+        writeln!(f, "max speed:    {:.3}", self.max_speed)?;
+        write!(f, "polarization: {:.3}", self.polarization)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::Vec3;
+
+    use crate::World;
+
+    #[test]
+    fn it_steps_the_requested_number_of_ticks() {
+        let mut world = World::new(10.0, 8);
+        world.add_boid_with_velocity(Vec3::ZERO, Vec3::new(0.5, 0.0, 0.0));
+        let mut seen = Vec::new();
+
+        let report = super::run(&mut world, 4, Duration::from_millis(10), |tick, _| {
+            seen.push(tick);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+
+        assert_eq!(seen, vec![1, 2, 3, 4]);
+        assert_eq!(report.ticks, 4);
+        assert_eq!(report.sim_time, Duration::from_millis(40));
+        assert_eq!(report.stats.boids, 1);
+        assert!((report.stats.centroid.x - 0.02).abs() < 1e-5);
+    }
+
+    #[test]
+    fn it_stops_at_the_first_error() {
+        let mut world = World::new(10.0, 8);
+
+        let result = super::run(&mut world, 10, Duration::from_millis(10), |tick, _| match tick {
+            3 => Err(tick),
+            _ => Ok(()),
+        });
+
+        assert_eq!(result.unwrap_err(), 3);
+        assert_eq!(world.tick(), 3);
+    }
+
+    #[test]
+    fn sim_time_survives_more_ticks_than_fit_in_a_u32() {
+        let ticks = 5_000_000_000;
+        assert_eq!(super::sim_time(Duration::from_millis(1), ticks), Duration::from_secs(5_000_000));
+        assert_eq!(super::sim_time(Duration::from_nanos(8_333_333), ticks), Duration::from_nanos(8_333_333 * ticks));
+        assert_eq!(super::sim_time(Duration::MAX, 2), Duration::MAX);
+    }
+}
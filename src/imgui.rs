@@ -1,6 +1,24 @@
 use std::{rc::Rc, time::Instant};
 
-use winit::event::{WindowEvent, Event};
+use winit::event::Event;
+
+use crate::camera::{self, CameraMode, Lens, Projection, View};
+
+const CAMERA_MODES: [(CameraMode, &str); 4] = [
+    (CameraMode::Fly, "Fly"),
+    (CameraMode::Orbit, "Orbit"),
+    (CameraMode::Chase, "Chase"),
+    (CameraMode::FirstPerson, "First person"),
+];
+
+/// The camera settings the UI edits, handed back to the renderer to apply
+/// once the UI has been drawn.
+pub(crate) struct CameraControls {
+    pub(crate) mode: CameraMode,
+    pub(crate) lens: Lens,
+    /// An axis view that was asked for.
+    pub(crate) view: Option<View>,
+}
 
 pub(crate) struct Imgui {
     window: Rc<winit::window::Window>,
@@ -50,16 +68,15 @@ impl Imgui {
         }
     }
 
-    pub(crate) fn render_ui<'a>(&'a mut self, device: &wgpu::Device, queue: &wgpu::Queue, pass: &mut wgpu::RenderPass<'a>) {
+    pub(crate) fn render_ui<'a>(&'a mut self, device: &wgpu::Device, queue: &wgpu::Queue, pass: &mut wgpu::RenderPass<'a>,
+        camera: &mut CameraControls) {
         // let delta_t = self.last_frame.elapsed();
         let now = Instant::now();
         self.imgui_context.io_mut().update_delta_time(now - self.last_frame);
         self.last_frame = now;
         self.imgui_platform.prepare_frame(self.imgui_context.io_mut(), &self.window).expect("failed to prepare frame");
         let ui = self.imgui_context.frame();
-        {
-            ui.show_demo_window(&mut true);
-        }
+        camera_window(&ui, camera);
         if self.last_cursor != ui.mouse_cursor() {
             self.last_cursor = ui.mouse_cursor();
             self.imgui_platform.prepare_render(&ui, &self.window);
@@ -68,7 +85,53 @@ impl Imgui {
         self.imgui_renderer.render(ui.render(), queue, device,pass).expect("failed to render imgui window");
     }
 
+    /// Whether the UI is using the mouse, e.g. because it's over a window.
+    pub(crate) fn wants_mouse(&self) -> bool {
+        self.imgui_context.io().want_capture_mouse
+    }
+
+    pub(crate) fn wants_keyboard(&self) -> bool {
+        self.imgui_context.io().want_capture_keyboard
+    }
+
     pub(crate) fn event(&mut self, event: &Event<()>) {
         self.imgui_platform.handle_event(self.imgui_context.io_mut(), &self.window, event);
     }
-}
\ No newline at end of file
+}
+
+impl CameraControls {
+    pub(crate) fn new(mode: CameraMode, lens: Lens) -> Self {
+        Self { mode, lens, view: None }
+    }
+}
+
+fn camera_window(ui: &imgui::Ui, controls: &mut CameraControls) {
+    imgui::Window::new("Camera")
+        .size([260.0, 190.0], imgui::Condition::FirstUseEver)
+        .build(ui, || {
+            let mut mode = CAMERA_MODES.iter().position(|&(mode, _)| mode == controls.mode).unwrap_or(0);
+            let names = CAMERA_MODES.map(|(_, name)| name);
+            if ui.combo_simple_string("mode", &mut mode, &names) {
+                controls.mode = CAMERA_MODES[mode].0;
+            }
+
+            let lens = &mut controls.lens;
+            ui.radio_button("perspective", &mut lens.projection, Projection::Perspective);
+            ui.same_line();
+            ui.radio_button("orthographic", &mut lens.projection, Projection::Orthographic);
+            imgui::Slider::new("fov", camera::MIN_FOV, camera::MAX_FOV)
+                .display_format("%.0f deg")
+                .build(ui, &mut lens.fov);
+            ui.input_float("near", &mut lens.near).build();
+            ui.input_float("far", &mut lens.far).build();
+
+            ui.separator();
+            for (view, label) in [(View::Top, "Top"), (View::Front, "Front"), (View::Side, "Side")] {
+                if ui.button(label) {
+                    controls.view = Some(view);
+                }
+                ui.same_line();
+            }
+            ui.new_line();
+        });
+}
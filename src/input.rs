@@ -0,0 +1,397 @@
+use std::{collections::HashSet, fmt, fs, io, path::Path};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::event::{DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::camera::Movement;
+
+/// Scroll distance, in pixels, that counts as one notch of a wheel.
+const PIXELS_PER_LINE: f32 = 50.0;
+/// How far the mouse can move, in pixels, between a button going down and
+/// coming back up for it to still count as a click rather than a drag.
+const CLICK_SLOP: f32 = 4.0;
+
+/// A key or mouse button that an action can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// Which keys and buttons do what, read from RON files. Every action can be
+/// bound to any number of keys and buttons, and actions left out keep
+/// their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub forward: Vec<Binding>,
+    pub back: Vec<Binding>,
+    pub left: Vec<Binding>,
+    pub right: Vec<Binding>,
+    pub up: Vec<Binding>,
+    pub down: Vec<Binding>,
+    pub pitch_up: Vec<Binding>,
+    pub pitch_down: Vec<Binding>,
+    pub yaw_left: Vec<Binding>,
+    pub yaw_right: Vec<Binding>,
+    pub sprint: Vec<Binding>,
+    pub slow: Vec<Binding>,
+    /// Held to steer the camera with the mouse.
+    pub mouse_look: Vec<Binding>,
+    /// Switches between flying and orbiting.
+    pub toggle_orbit: Vec<Binding>,
+    /// Moves the orbit on to the next target, re-centring it.
+    pub orbit_target: Vec<Binding>,
+    /// Held to circle the orbit target by dragging the mouse.
+    pub orbit: Vec<Binding>,
+    /// Held along with `orbit` to pan instead.
+    pub pan: Vec<Binding>,
+    /// Clicked on a boid to select it for orbiting, chasing or riding along.
+    pub select: Vec<Binding>,
+    /// Switches between chasing the selected boid and flying.
+    pub chase: Vec<Binding>,
+    /// Switches between riding along on the selected boid and flying.
+    pub first_person: Vec<Binding>,
+    /// Look straight down, along or across the world, orthographically.
+    pub top_view: Vec<Binding>,
+    pub front_view: Vec<Binding>,
+    pub side_view: Vec<Binding>,
+    /// Switches between perspective and orthographic projection.
+    pub toggle_projection: Vec<Binding>,
+    pub screenshot: Vec<Binding>,
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+/// Which keys and buttons are held down, and what happened since the last
+/// frame. Fed every input event as it arrives and read once per frame.
+#[derive(Debug, Default)]
+pub(crate) struct InputState {
+    down: HashSet<Binding>,
+    /// Went down since the last frame, even if they've been let go since.
+    pressed: HashSet<Binding>,
+    mouse_delta: Vec2,
+    /// In notches of the scroll wheel.
+    scroll: f32,
+    /// Where the cursor is, in pixels from the top left of the window.
+    cursor: Vec2,
+    /// Where the cursor was when a mouse button last went down, and how far
+    /// the mouse has moved since.
+    press_cursor: Vec2,
+    drag: f32,
+    /// Mouse buttons pressed and let go without dragging since the last
+    /// frame, with where the cursor was.
+    clicks: Vec<(Binding, Vec2)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::{Key, Mouse};
+        use VirtualKeyCode as K;
+
+        Self {
+            forward: vec![Key(K::W)],
+            back: vec![Key(K::S)],
+            left: vec![Key(K::A)],
+            right: vec![Key(K::D)],
+            up: vec![Key(K::E)],
+            down: vec![Key(K::Q)],
+            pitch_up: vec![Key(K::Up)],
+            pitch_down: vec![Key(K::Down)],
+            yaw_left: vec![Key(K::Left)],
+            yaw_right: vec![Key(K::Right)],
+            sprint: vec![Key(K::LShift), Key(K::RShift)],
+            slow: vec![Key(K::LAlt), Key(K::RAlt)],
+            mouse_look: vec![Mouse(MouseButton::Right)],
+            toggle_orbit: vec![Key(K::Tab)],
+            orbit_target: vec![Key(K::T)],
+            orbit: vec![Mouse(MouseButton::Left)],
+            pan: vec![Key(K::LControl), Key(K::RControl)],
+            select: vec![Mouse(MouseButton::Left)],
+            chase: vec![Key(K::C)],
+            first_person: vec![Key(K::V)],
+            top_view: vec![Key(K::Numpad7)],
+            front_view: vec![Key(K::Numpad1)],
+            side_view: vec![Key(K::Numpad3)],
+            toggle_projection: vec![Key(K::Numpad5)],
+            screenshot: vec![Key(K::F12)],
+        }
+    }
+}
+
+impl Bindings {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindingsError> {
+        Self::from_ron(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, BindingsError> {
+        Ok(ron::de::from_str(source)?)
+    }
+
+    fn actions(&self) -> [&[Binding]; 25] {
+        [
+            &self.forward, &self.back, &self.left, &self.right, &self.up, &self.down,
+            &self.pitch_up, &self.pitch_down, &self.yaw_left, &self.yaw_right,
+            &self.sprint, &self.slow, &self.mouse_look, &self.toggle_orbit,
+            &self.orbit_target, &self.orbit, &self.pan, &self.select, &self.chase,
+            &self.first_person, &self.top_view, &self.front_view, &self.side_view,
+            &self.toggle_projection, &self.screenshot,
+        ]
+    }
+
+    /// Whether `binding` does anything.
+    pub(crate) fn is_bound(&self, binding: Binding) -> bool {
+        self.actions().iter().any(|action| action.contains(&binding))
+    }
+
+    /// The camera controls `input` is holding down.
+    pub(crate) fn movement(&self, input: &InputState) -> Movement {
+        let held = |action: &[Binding]| input.any_down(action);
+        Movement {
+            strafe_forward: held(&self.forward),
+            strafe_back: held(&self.back),
+            strafe_left: held(&self.left),
+            strafe_right: held(&self.right),
+            up: held(&self.up),
+            down: held(&self.down),
+            pitch_up: held(&self.pitch_up),
+            pitch_down: held(&self.pitch_down),
+            yaw_left: held(&self.yaw_left),
+            yaw_right: held(&self.yaw_right),
+            sprint: held(&self.sprint),
+            slow: held(&self.slow),
+        }
+    }
+}
+
+impl InputState {
+    /// Records a key, mouse button or scroll event, returning the key or
+    /// button involved, if any.
+    pub(crate) fn handle_window_event(&mut self, event: &WindowEvent) -> Option<Binding> {
+        let (binding, state) = match event {
+            WindowEvent::KeyboardInput { input: KeyboardInput { state, virtual_keycode: Some(key), .. }, .. } => {
+                (Binding::Key(*key), *state)
+            }
+            WindowEvent::MouseInput { state, button, .. } => (Binding::Mouse(*button), *state),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Vec2::new(position.x as f32, position.y as f32);
+                return None;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                return None;
+            }
+            // keys let go of while the window is in the background never
+            // arrive, so forget them all
+            WindowEvent::Focused(false) => {
+                self.down.clear();
+                return None;
+            }
+            _ => return None,
+        };
+        match state {
+            // key repeat sends more presses for a key that's already down
+            ElementState::Pressed => {
+                if self.down.insert(binding) {
+                    self.pressed.insert(binding);
+                    if let Binding::Mouse(_) = binding {
+                        self.press_cursor = self.cursor;
+                        self.drag = 0.0;
+                    }
+                }
+            }
+            ElementState::Released => {
+                if self.down.remove(&binding) && matches!(binding, Binding::Mouse(_)) && self.drag < CLICK_SLOP {
+                    self.clicks.push((binding, self.press_cursor));
+                }
+            }
+        }
+        Some(binding)
+    }
+
+    /// Records raw mouse motion, which keeps coming even when the cursor is
+    /// grabbed at the edge of the window.
+    pub(crate) fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            let delta = Vec2::new(*x as f32, *y as f32);
+            self.mouse_delta += delta;
+            self.drag += delta.length();
+        }
+    }
+
+    pub(crate) fn any_down(&self, action: &[Binding]) -> bool {
+        action.iter().any(|b| self.down.contains(b))
+    }
+
+    /// Whether any of `action` went down since the last frame.
+    pub(crate) fn any_pressed(&self, action: &[Binding]) -> bool {
+        action.iter().any(|b| self.pressed.contains(b))
+    }
+
+    /// Where the cursor was when any of `action` was last clicked since the
+    /// last frame, in pixels from the top left of the window.
+    pub(crate) fn clicked(&self, action: &[Binding]) -> Option<Vec2> {
+        self.clicks.iter().rev().find(|(b, _)| action.contains(b)).map(|&(_, at)| at)
+    }
+
+    /// Mouse movement since the last frame, in pixels.
+    pub(crate) fn mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+
+    /// Scrolling since the last frame, in notches of the wheel.
+    pub(crate) fn scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    /// Forgets everything that only lasts a frame. Keys and buttons held
+    /// down stay down.
+    pub(crate) fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.clicks.clear();
+        self.mouse_delta = Vec2::ZERO;
+        self.scroll = 0.0;
+    }
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingsError::Io(e) => write!(f, "could not read key bindings: {}", e),
+            BindingsError::Parse(e) => write!(f, "malformed key bindings: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BindingsError {}
+
+impl From<io::Error> for BindingsError {
+    fn from(e: io::Error) -> Self {
+        BindingsError::Io(e)
+    }
+}
+
+impl From<ron::Error> for BindingsError {
+    fn from(e: ron::Error) -> Self {
+        BindingsError::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use winit::{
+        dpi::PhysicalPosition,
+        event::{DeviceEvent, DeviceId, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    };
+
+    use super::{Binding, Bindings, InputState};
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput { scancode: 0, state, virtual_keycode: Some(key), modifiers: Default::default() },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn mouse(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput { device_id: unsafe { DeviceId::dummy() }, state, button, modifiers: Default::default() }
+    }
+
+    #[allow(deprecated)]
+    fn cursor_at(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: unsafe { DeviceId::dummy() },
+            position: PhysicalPosition::new(x, y),
+            modifiers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn the_bundled_bindings_are_the_defaults() {
+        let bindings = Bindings::from_ron(include_str!("../bindings/default.ron")).unwrap();
+        assert_eq!(bindings, Bindings::default());
+    }
+
+    #[test]
+    fn missing_actions_keep_their_defaults() {
+        let bindings = Bindings::from_ron("(forward: [Key(Z), Mouse(Left)], back: [])").unwrap();
+        assert_eq!(bindings.forward, [Binding::Key(VirtualKeyCode::Z), Binding::Mouse(MouseButton::Left)]);
+        assert!(bindings.back.is_empty());
+        assert_eq!(bindings.left, Bindings::default().left);
+        assert!(!bindings.is_bound(Binding::Key(VirtualKeyCode::W)));
+        assert!(bindings.is_bound(Binding::Key(VirtualKeyCode::Z)));
+    }
+
+    #[test]
+    fn keys_stay_held_until_released() {
+        let bindings = Bindings::default();
+        let mut input = InputState::default();
+
+        input.handle_window_event(&key(VirtualKeyCode::W, ElementState::Pressed));
+        assert!(bindings.movement(&input).strafe_forward);
+        assert!(input.any_pressed(&bindings.forward));
+
+        // still held next frame, though no new event came in
+        input.end_frame();
+        assert!(bindings.movement(&input).strafe_forward);
+        assert!(!input.any_pressed(&bindings.forward));
+
+        // key repeat isn't a new press
+        input.handle_window_event(&key(VirtualKeyCode::W, ElementState::Pressed));
+        assert!(!input.any_pressed(&bindings.forward));
+
+        // letting go doesn't move anything
+        input.handle_window_event(&key(VirtualKeyCode::W, ElementState::Released));
+        let movement = bindings.movement(&input);
+        assert!(!movement.strafe_forward && !movement.strafe_back);
+    }
+
+    #[test]
+    fn a_tap_between_frames_still_counts_as_a_press() {
+        let bindings = Bindings::default();
+        let mut input = InputState::default();
+        input.handle_window_event(&key(VirtualKeyCode::F12, ElementState::Pressed));
+        input.handle_window_event(&key(VirtualKeyCode::F12, ElementState::Released));
+        assert!(input.any_pressed(&bindings.screenshot));
+        assert!(!input.any_down(&bindings.screenshot));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let bindings = Bindings::default();
+        let mut input = InputState::default();
+        input.handle_window_event(&key(VirtualKeyCode::LShift, ElementState::Pressed));
+        input.handle_window_event(&WindowEvent::Focused(false));
+        assert!(!bindings.movement(&input).sprint);
+    }
+
+    #[test]
+    fn clicks_are_told_apart_from_drags() {
+        let bindings = Bindings::default();
+        let mut input = InputState::default();
+        input.handle_window_event(&cursor_at(30.0, 40.0));
+        input.handle_window_event(&mouse(MouseButton::Left, ElementState::Pressed));
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (1.0, 1.0) });
+        input.handle_window_event(&mouse(MouseButton::Left, ElementState::Released));
+        assert_eq!(input.clicked(&bindings.select), Some(Vec2::new(30.0, 40.0)));
+
+        input.end_frame();
+        assert_eq!(input.clicked(&bindings.select), None);
+        input.handle_window_event(&mouse(MouseButton::Left, ElementState::Pressed));
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (25.0, 0.0) });
+        input.handle_window_event(&mouse(MouseButton::Left, ElementState::Released));
+        assert_eq!(input.clicked(&bindings.select), None);
+    }
+}
@@ -1,57 +1,355 @@
 #![allow(dead_code)]
 
-use std::{rc::Rc, time::{Duration}};
-use glam::{Mat4, Vec3};
-use winit::{window::Window, dpi::PhysicalSize, event::{WindowEvent, Event}};
-use world::World;
+use std::{path::PathBuf, rc::Rc, time::Duration};
+use glam::{Mat3, Quat, Vec3, Vec4};
+use winit::{window::Window, dpi::PhysicalSize, event::{DeviceEvent, WindowEvent, Event}};
 use renderer::Renderer;
 
+pub use camera::{CameraMode, OrbitTarget};
+pub use mesh::MeshError;
+pub use renderer::{Lighting, RendererError};
+use timestep::FixedTimestep;
+use coloring::Coloring;
+use input::Bindings;
+
+pub use world::World;
+
+/// How far boids roll into a turn, in radians per unit of sideways
+/// acceleration.
+const BANK_PER_ACCEL: f32 = 1.0;
+const MAX_BANK: f32 = std::f32::consts::FRAC_PI_3;
+/// How close a click has to pass to a boid's center to pick it.
+const PICK_RADIUS: f32 = 0.4;
+
 mod imgui;
 mod camera;
-mod world;
+pub mod input;
+pub mod coloring;
+mod mesh;
+pub mod world;
+pub mod cli;
+pub mod headless;
+pub mod offscreen;
+pub mod recording;
+pub mod scenario;
+pub mod timestep;
 mod renderer;
 
+/// Settings for the windowed app that aren't part of the simulation itself.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub backend: Backend,
+    pub vsync: Vsync,
+    pub tick_rate: f64,
+    /// Where screenshots taken with F12 are written.
+    pub screenshot_dir: PathBuf,
+    /// OBJ or glTF model to draw boids with, instead of the built-in dart.
+    pub mesh: Option<PathBuf>,
+    pub camera_mode: CameraMode,
+    /// The boid selected at startup, for orbiting, chasing or riding along.
+    pub follow: Option<usize>,
+    /// Where a chasing camera sits in the followed boid's frame, with +Z
+    /// ahead of it and +Y above it.
+    pub chase_offset: Vec3,
+    pub lighting: Lighting,
+    pub coloring: Coloring,
+    pub bindings: Bindings,
+}
+
+/// Which graphics API to render with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum Backend {
+    /// Whatever `WGPU_BACKEND` names, or the best primary backend available.
+    Auto,
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+/// How frames are paced against the display's refresh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum Vsync {
+    /// Present immediately, possibly tearing.
+    Off,
+    /// Wait for vertical blank.
+    On,
+    /// Wait for vertical blank, replacing queued frames with newer ones.
+    Mailbox,
+}
+
+/// Where one boid's mesh is drawn. The camera's view-projection is applied
+/// on the GPU, so this is all that's uploaded per boid.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 struct BoidInstance {
-    mvp: Mat4,
+    position: Vec3,
+    scale: f32,
+    rotation: Quat,
+    /// Replaces the mesh's own colors in proportion to its alpha.
+    tint: Vec4,
 }
 
 pub struct App {
     world: world::World,
     renderer: renderer::Renderer,
     instance_data: Vec<BoidInstance>,
+    coloring: Coloring,
+    tints: Vec<Vec4>,
+    timestep: FixedTimestep,
+    /// Boid positions as of the tick before the current one, for interpolation.
+    prev_positions: Vec<Vec3>,
 }
 
 impl App {
-    pub async fn new(window: Rc<Window>) -> Self {
-        Self {
-            world: World::new(0.0, 12),
-            renderer: Renderer::new(window).await,
+    pub async fn new(window: Rc<Window>, world: World, config: Config) -> Result<Self, RendererError> {
+        Ok(Self {
+            world,
+            renderer: Renderer::new(window, &config).await?,
             instance_data: Vec::with_capacity(50),
-        }
+            coloring: config.coloring,
+            tints: Vec::new(),
+            timestep: FixedTimestep::from_hz(config.tick_rate),
+            prev_positions: Vec::new(),
+        })
+    }
+
+    pub fn set_tick_rate(&mut self, hz: f64) {
+        self.timestep = FixedTimestep::from_hz(hz);
     }
 
-    pub fn update(&mut self) {
-        self.world.update();
+    /// Runs however many fixed simulation ticks fit into `delta_t` of
+    /// wall-clock time.
+    pub fn update(&mut self, delta_t: Duration) {
+        let ticks = self.timestep.advance(delta_t);
+        for tick in 0..ticks {
+            if tick + 1 == ticks {
+                self.prev_positions.clear();
+                self.prev_positions.extend(self.world.boids().iter().map(|b| b.position()));
+            }
+            self.world.update(self.timestep.step());
+        }
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.renderer.resize(new_size)
     }
 
+    /// Handles a window event, returning whether it was used up.
     pub fn input(&mut self, win_event: &WindowEvent, event: &Event<()>) -> bool {
         self.renderer.input(win_event, event)
     }
 
+    pub fn device_input(&mut self, event: &DeviceEvent) {
+        self.renderer.device_input(event)
+    }
+
     pub fn render(&mut self, delta_t: Duration) {
-        let cur_cam = self.renderer.camera();
-        self.world.fill_instance_buffer(&mut self.instance_data, cur_cam.view_mat(), cur_cam.perspective_mat());
+        self.coloring.tints(&self.world, &mut self.tints);
+        fill_instances(&self.world, &self.prev_positions, self.timestep.alpha(), &self.tints, &mut self.instance_data);
+        if let Some((origin, dir)) = self.renderer.pick_ray() {
+            if let Some(i) = pick(origin, dir, &self.instance_data) {
+                self.renderer.select_boid(i);
+            }
+        }
+        if let Some(boid) = self.instance_data.get(self.renderer.selected_boid()) {
+            self.renderer.set_follow_pose(boid.position, boid.rotation);
+        }
+        self.renderer.set_orbit_point(orbit_point(self.renderer.orbit_target(), &self.instance_data));
         self.renderer.fill_instance_buffer(&self.instance_data);
         self.renderer.render(delta_t).expect("rendering failed somehow");
     }
 
+    pub fn set_lighting(&self, lighting: &Lighting) {
+        self.renderer.set_lighting(lighting);
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.renderer.set_camera_mode(mode);
+    }
+
+    pub fn set_orbit_target(&mut self, target: OrbitTarget) {
+        self.renderer.set_orbit_target(target);
+    }
+
+    /// Selects the boid with index `i` to orbit, chase or ride along on.
+    pub fn select_boid(&mut self, i: usize) {
+        self.renderer.select_boid(i);
+    }
+
+    pub fn set_coloring(&mut self, coloring: Coloring) {
+        self.coloring = coloring;
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
     pub fn add_boid(&mut self, pos: Vec3) {
         self.world.add_boid(pos);
     }
 }
+
+/// Fills `out` with one instance per boid, drawn `alpha` of the way from
+/// `prev_positions` (where known) to where it is now and tinted with the
+/// matching entry of `tints`, if any.
+fn fill_instances(world: &World, prev_positions: &[Vec3], alpha: f32, tints: &[Vec4], out: &mut Vec<BoidInstance>) {
+    // a boid that just wrapped around the world would otherwise be drawn
+    // sweeping across the whole volume
+    let max_jump = 0.5 * world.side_len();
+    out.clear();
+    for (i, boid) in world.boids().iter().enumerate() {
+        let pos = match prev_positions.get(i) {
+            Some(prev) if prev.distance(boid.position()) < max_jump => prev.lerp(boid.position(), alpha),
+            _ => boid.position(),
+        };
+        let rotation = orientation(boid.velocity(), boid.acceleration());
+        let tint = tints.get(i).copied().unwrap_or(coloring::NO_TINT);
+        out.push(BoidInstance { position: pos, scale: 1.0, rotation, tint });
+    }
+}
+
+/// Where `target` is drawn among `instances`. A boid that doesn't exist
+/// (any more) leaves the camera orbiting the world's center.
+fn orbit_point(target: OrbitTarget, instances: &[BoidInstance]) -> Vec3 {
+    match target {
+        OrbitTarget::WorldCenter => Vec3::ZERO,
+        OrbitTarget::Centroid if instances.is_empty() => Vec3::ZERO,
+        OrbitTarget::Centroid => {
+            instances.iter().fold(Vec3::ZERO, |sum, i| sum + i.position) / instances.len() as f32
+        }
+        OrbitTarget::Boid(i) => instances.get(i).map_or(Vec3::ZERO, |i| i.position),
+    }
+}
+
+/// The nearest of `instances` along the ray from `origin` along `dir`
+/// that it passes within `PICK_RADIUS` of, if any.
+fn pick(origin: Vec3, dir: Vec3, instances: &[BoidInstance]) -> Option<usize> {
+    instances.iter().enumerate()
+        .filter_map(|(i, instance)| {
+            let to = instance.position - origin;
+            let along = to.dot(dir);
+            let miss = (to - dir * along).length();
+            if along > 0.0 && miss <= PICK_RADIUS * instance.scale { Some((i, along)) } else { None }
+        })
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i)
+}
+
+/// The rotation taking a mesh facing +Z with +Y up to one heading along
+/// `velocity`, rolled into the turn by however much of `acceleration` is
+/// sideways. A boid at rest keeps the mesh's own orientation.
+fn orientation(velocity: Vec3, acceleration: Vec3) -> Quat {
+    let forward = match velocity.try_normalize() {
+        Some(forward) => forward,
+        None => return Quat::IDENTITY,
+    };
+    // climbing or diving straight up has no "up" to speak of, so borrow one
+    let reference_up = if forward.y.abs() > 0.999 { Vec3::Z } else { Vec3::Y };
+    let right = reference_up.cross(forward).normalize();
+    let up = forward.cross(right);
+    let heading = Quat::from_mat3(&Mat3::from_cols(right, up, forward));
+
+    // a positive angle about `forward` would tip `up` away from `right`
+    let bank = (-BANK_PER_ACCEL * acceleration.dot(right)).clamp(-MAX_BANK, MAX_BANK);
+    (Quat::from_axis_angle(forward, bank) * heading).normalize()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Auto,
+            vsync: Vsync::Off,
+            tick_rate: timestep::DEFAULT_TICK_RATE,
+            screenshot_dir: PathBuf::from("."),
+            mesh: None,
+            camera_mode: CameraMode::Fly,
+            follow: None,
+            chase_offset: camera::DEFAULT_CHASE_OFFSET,
+            lighting: Lighting::default(),
+            coloring: Coloring::default(),
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn instances_interpolate_but_not_across_a_wrap() {
+        let mut world = World::new(10.0, 4);
+        world.add_boid(Vec3::new(1.0, 0.0, 0.0));
+        world.add_boid(Vec3::new(4.9, 0.0, 0.0));
+        let prev = [Vec3::ZERO, Vec3::new(-4.9, 0.0, 0.0)];
+
+        let mut out = Vec::new();
+        fill_instances(&world, &prev, 0.25, &[Vec4::X], &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].position, Vec3::new(0.25, 0.0, 0.0));
+        assert_eq!(out[1].position, Vec3::new(4.9, 0.0, 0.0));
+        assert_eq!((out[0].tint, out[1].tint), (Vec4::X, coloring::NO_TINT));
+        assert_eq!(std::mem::size_of::<BoidInstance>(), 48);
+    }
+
+    #[test]
+    fn orientation_faces_along_velocity() {
+        for velocity in [Vec3::X, Vec3::new(0.3, -0.2, 0.9), Vec3::Y, -Vec3::Y, Vec3::new(-1.0, 0.0, -1.0)] {
+            let rotation = orientation(velocity, Vec3::ZERO);
+            assert!(rotation.is_normalized());
+            assert!((rotation * Vec3::Z).abs_diff_eq(velocity.normalize(), 1e-5), "{:?}", velocity);
+        }
+        assert_eq!(orientation(Vec3::ZERO, Vec3::X), Quat::IDENTITY);
+        // level flight stays level
+        let level = orientation(Vec3::X, Vec3::ZERO);
+        assert!((level * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-5));
+    }
+
+    #[test]
+    fn orientation_banks_into_turns() {
+        let velocity = Vec3::Z;
+        let right = Vec3::Y.cross(velocity);
+        let up = |accel| orientation(velocity, accel) * Vec3::Y;
+
+        // speeding up or slowing down doesn't roll
+        assert!(up(2.0 * velocity).abs_diff_eq(Vec3::Y, 1e-5));
+
+        let gentle = up(0.2 * right);
+        let hard = up(0.5 * right);
+        assert!(gentle.dot(right) > 0.0);
+        assert!(hard.dot(right) > gentle.dot(right));
+        assert!(up(-0.5 * right).dot(right) < 0.0);
+
+        // the roll is capped
+        let extreme = up(100.0 * right);
+        assert!((extreme.angle_between(Vec3::Y) - MAX_BANK).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_targets_follow_the_drawn_boids() {
+        let instance = |x: f32| BoidInstance { position: Vec3::new(x, 1.0, 0.0), scale: 1.0, rotation: Quat::IDENTITY, tint: coloring::NO_TINT };
+        let instances = [instance(1.0), instance(3.0)];
+        assert_eq!(orbit_point(OrbitTarget::WorldCenter, &instances), Vec3::ZERO);
+        assert_eq!(orbit_point(OrbitTarget::Centroid, &instances), Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(orbit_point(OrbitTarget::Boid(1), &instances), Vec3::new(3.0, 1.0, 0.0));
+        assert_eq!(orbit_point(OrbitTarget::Boid(2), &instances), Vec3::ZERO);
+        assert_eq!(orbit_point(OrbitTarget::Centroid, &[]), Vec3::ZERO);
+    }
+
+    #[test]
+    fn clicks_pick_the_nearest_boid_under_the_cursor() {
+        let instance = |position| BoidInstance { position, scale: 1.0, rotation: Quat::IDENTITY, tint: coloring::NO_TINT };
+        let instances = [
+            instance(Vec3::new(0.0, 0.0, 5.0)),
+            instance(Vec3::new(0.3, 0.0, 2.0)),
+            instance(Vec3::new(0.0, 0.0, -1.0)),
+            instance(Vec3::new(2.0, 0.0, 1.0)),
+        ];
+        // the boid behind the eye doesn't count
+        assert_eq!(pick(Vec3::ZERO, Vec3::Z, &instances), Some(1));
+        assert_eq!(pick(Vec3::ZERO, Vec3::X, &instances), None);
+    }
+}
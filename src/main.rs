@@ -1,30 +1,34 @@
-use std::{rc::Rc, time::Instant};
+use std::{error::Error, rc::Rc, time::Instant};
 
-use glam::Vec3;
+use clap::Parser;
+use iridium::cli::Args;
 use winit::{
+    dpi::PhysicalSize,
     event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder},
 };
 
-async fn run() {
-    env_logger::init();
+async fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let world = args.world()?;
     let event_loop = EventLoop::new();
-    let window = Rc::new(WindowBuilder::new().build(&event_loop).unwrap());
+    let mut builder = WindowBuilder::new()
+        .with_title("iridium")
+        .with_inner_size(PhysicalSize::new(args.width, args.height));
+    if args.fullscreen {
+        builder = builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let window = Rc::new(builder.build(&event_loop)?);
     
-    let mut app = iridium::App::new(window.clone()).await;
-    app.add_boid(Vec3::new(0.0, 0.0, 0.0));
+    let mut app = iridium::App::new(window.clone(), world, args.config()?).await?;
     let mut cur = Instant::now();
     
     event_loop.run(move |event, _, control_flow| {
-        let new_time = Instant::now();
-        let delta_t = new_time - cur;
-        cur = new_time;
         match event {
             Event::WindowEvent {
                 event: ref win_event,
                 window_id,
-            } if window_id == window.id() => if !app.input(win_event, &event) { // UPDATED!
+            } if window_id == window.id() && !app.input(win_event, &event) => {
                 match win_event {
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
@@ -45,16 +49,45 @@ async fn run() {
                     _ => {}
                 }
             },
-            Event::RedrawEventsCleared => {
+            Event::DeviceEvent { event: ref device_event, .. } => {
+                app.device_input(device_event);
+            },
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            },
+            Event::RedrawRequested(window_id) if window_id == window.id() => {
+                let new_time = Instant::now();
+                let delta_t = new_time - cur;
+                cur = new_time;
+                app.update(delta_t);
+                app.render(delta_t);
+            },
+            Event::LoopDestroyed => {
+                if let Some(path) = &args.save {
+                    if let Err(e) = app.world().save(path) {
+                        eprintln!("error: {}: {}", path.display(), e);
+                    }
+                }
             },
             _ => {}
         }
-        app.update();
-        app.render(delta_t);
-
     });
 }
 
 fn main() {
-    pollster::block_on(run());
+    env_logger::init();
+    let args = Args::parse();
+    if let Err(e) = args.check_modes() {
+        e.exit();
+    }
+    let result = if args.headless {
+        iridium::cli::run_headless(&args)
+    } else {
+        pollster::block_on(run(args))
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
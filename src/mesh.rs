@@ -0,0 +1,469 @@
+use std::{f32::consts::TAU, fmt, path::Path};
+
+use glam::{const_vec4, Mat3, Mat4, Vec2, Vec3, Vec4};
+
+/// How long a boid is along its heading, whatever units its mesh was
+/// modelled in.
+const BOID_LENGTH: f32 = 0.6;
+/// Used for meshes whose files give neither vertex nor material colors.
+const DEFAULT_COLOR: Vec4 = const_vec4!([0.45, 0.05, 0.05, 1.0]);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct Vertex {
+    // first, as the only field that's 16-byte aligned
+    pub(crate) color: Vec4,
+    pub(crate) position: Vec3,
+    pub(crate) normal: Vec3,
+    pub(crate) uv: Vec2,
+}
+
+/// The geometry every boid instance is drawn with, in model space: the boid
+/// faces +Z with +Y up.
+#[derive(Clone, Debug)]
+pub(crate) struct Mesh {
+    pub(crate) vertices: Vec<Vertex>,
+    pub(crate) indices: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum MeshError {
+    Obj(tobj::LoadError),
+    Gltf(gltf::Error),
+    /// The file's extension isn't `.obj`, `.gltf` or `.glb`.
+    UnknownFormat,
+    /// The file loaded but had no triangles in it.
+    Empty,
+    /// A triangle refers to vertex `index`, but there are only `len`.
+    IndexOutOfRange { index: u32, len: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Obj(e) => write!(f, "invalid OBJ: {}", e),
+            MeshError::Gltf(e) => write!(f, "invalid glTF: {}", e),
+            MeshError::UnknownFormat => write!(f, "unknown mesh format (expected .obj, .gltf or .glb)"),
+            MeshError::Empty => write!(f, "mesh has no triangles"),
+            MeshError::IndexOutOfRange { index, len } => {
+                write!(f, "mesh refers to vertex {} but only has {} vertices", index, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Obj(e) => Some(e),
+            MeshError::Gltf(e) => Some(e),
+            MeshError::UnknownFormat | MeshError::Empty | MeshError::IndexOutOfRange { .. } => None,
+        }
+    }
+}
+
+impl From<tobj::LoadError> for MeshError {
+    fn from(e: tobj::LoadError) -> Self {
+        MeshError::Obj(e)
+    }
+}
+
+impl From<gltf::Error> for MeshError {
+    fn from(e: gltf::Error) -> Self {
+        MeshError::Gltf(e)
+    }
+}
+
+impl Mesh {
+    /// A dart: a cone flattened along Y so that it's wider than it is tall,
+    /// which makes both heading and banking visible.
+    pub(crate) fn arrow() -> Self {
+        const SEGMENTS: usize = 8;
+        const TIP: f32 = 0.35;
+        const TAIL: f32 = -0.25;
+        const HALF_WIDTH: f32 = 0.18;
+        const HALF_HEIGHT: f32 = 0.06;
+
+        let tip_color = Vec4::new(0.95, 0.55, 0.55, 1.0);
+
+        let tip = Vec3::new(0.0, 0.0, TIP);
+        let tail = Vec3::new(0.0, 0.0, TAIL);
+        let rim: Vec<Vec3> = (0..SEGMENTS)
+            .map(|i| {
+                let angle = TAU * i as f32 / SEGMENTS as f32;
+                Vec3::new(HALF_WIDTH * angle.cos(), HALF_HEIGHT * angle.sin(), TAIL)
+            })
+            .collect();
+
+        // wound counter-clockwise as seen from outside with the renderer's
+        // left-handed camera, so back faces can be culled. Every face gets
+        // its own vertices so that it's shaded flat.
+        let mut mesh = Self { vertices: Vec::with_capacity(6 * SEGMENTS), indices: Vec::with_capacity(6 * SEGMENTS) };
+        for i in 0..SEGMENTS {
+            let (a, b) = (rim[i], rim[(i + 1) % SEGMENTS]);
+            mesh.push_flat_triangle([tip, b, a], [tip_color, DEFAULT_COLOR, DEFAULT_COLOR]);
+            mesh.push_flat_triangle([tail, a, b], [DEFAULT_COLOR; 3]);
+        }
+        mesh
+    }
+
+    /// Loads a mesh from a Wavefront OBJ (`.obj`) or glTF (`.gltf`, `.glb`)
+    /// file, centred on the origin and scaled to the size of a boid.
+    ///
+    /// Both formats are right-handed, so meshes are mirrored across X to
+    /// keep their faces' winding and their +Z forward in the renderer's
+    /// left-handed space. Missing normals are computed from the faces and
+    /// missing colors come from the material, if any.
+    pub(crate) fn load(path: &Path) -> Result<Self, MeshError> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+        let mesh = match extension.as_deref() {
+            Some("obj") => {
+                let (models, materials) = tobj::load_obj(path, &Self::obj_options())?;
+                Self::from_obj(&models, &materials.unwrap_or_default())
+            }
+            Some("gltf") | Some("glb") => {
+                let gltf::Gltf { document, blob } = gltf::Gltf::open(path)?;
+                let buffers = gltf::import_buffers(&document, path.parent(), blob)?;
+                Self::from_gltf(&document, &buffers)
+            }
+            _ => return Err(MeshError::UnknownFormat),
+        };
+        mesh.finish()
+    }
+
+    fn obj_options() -> tobj::LoadOptions {
+        tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() }
+    }
+
+    fn from_obj(models: &[tobj::Model], materials: &[tobj::Material]) -> Self {
+        let mut mesh = Self { vertices: Vec::new(), indices: Vec::new() };
+        for model in models {
+            let obj = &model.mesh;
+            let material_color = obj
+                .material_id
+                .and_then(|id| materials.get(id))
+                .map(|m| Vec3::from(m.diffuse).extend(1.0))
+                .unwrap_or(DEFAULT_COLOR);
+            let base = mesh.vertices.len() as u32;
+            for i in 0..obj.positions.len() / 3 {
+                let vec3 = |data: &[f32]| data.get(3 * i..3 * i + 3).map(Vec3::from_slice);
+                mesh.vertices.push(Vertex {
+                    position: vec3(&obj.positions).unwrap_or_default(),
+                    normal: vec3(&obj.normals).unwrap_or_default(),
+                    color: vec3(&obj.vertex_color).map_or(material_color, |c| c.extend(1.0)),
+                    uv: obj.texcoords.get(2 * i..2 * i + 2).map(Vec2::from_slice).unwrap_or_default(),
+                });
+            }
+            mesh.indices.extend(obj.indices.iter().map(|i| base + i));
+        }
+        mesh
+    }
+
+    /// Every triangle primitive in the document's default scene (or first
+    /// scene), with node transforms applied.
+    fn from_gltf(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Self {
+        fn visit(node: gltf::Node, parent: Mat4, buffers: &[gltf::buffer::Data], mesh: &mut Mesh) {
+            let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+            let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+            for primitive in node.mesh().iter().flat_map(|m| m.primitives()) {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+                let positions: Vec<Vec3> = match reader.read_positions() {
+                    Some(positions) => positions.map(Vec3::from).collect(),
+                    None => continue,
+                };
+                let mut normals = reader.read_normals().into_iter().flatten().map(Vec3::from);
+                let mut colors = reader.read_colors(0).into_iter().flat_map(|c| c.into_rgba_f32()).map(Vec4::from);
+                let mut uvs = reader.read_tex_coords(0).into_iter().flat_map(|t| t.into_f32()).map(Vec2::from);
+                let material_color = Vec4::from(primitive.material().pbr_metallic_roughness().base_color_factor());
+
+                let base = mesh.vertices.len() as u32;
+                for position in positions.iter() {
+                    mesh.vertices.push(Vertex {
+                        position: transform.transform_point3(*position),
+                        normal: normals.next().map_or(Vec3::ZERO, |n| (normal_matrix * n).normalize_or_zero()),
+                        color: colors.next().unwrap_or(material_color),
+                        uv: uvs.next().unwrap_or_default(),
+                    });
+                }
+                match reader.read_indices() {
+                    Some(indices) => mesh.indices.extend(indices.into_u32().map(|i| base + i)),
+                    None => mesh.indices.extend(base..base + positions.len() as u32),
+                }
+            }
+            for child in node.children() {
+                visit(child, transform, buffers, mesh);
+            }
+        }
+
+        let mut mesh = Self { vertices: Vec::new(), indices: Vec::new() };
+        if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+            for node in scene.nodes() {
+                visit(node, Mat4::IDENTITY, buffers, &mut mesh);
+            }
+        }
+        mesh
+    }
+
+    /// Fills in missing normals, then moves a right-handed mesh fresh from a
+    /// file into boid space.
+    fn finish(mut self) -> Result<Self, MeshError> {
+        self.indices.truncate(self.indices.len() - self.indices.len() % 3);
+        if self.indices.is_empty() {
+            return Err(MeshError::Empty);
+        }
+        let len = self.vertices.len();
+        if let Some(&index) = self.indices.iter().find(|&&i| i as usize >= len) {
+            return Err(MeshError::IndexOutOfRange { index, len });
+        }
+
+        // area-weighted face normals, counter-clockwise being outwards in a
+        // right-handed frame
+        let mut face_normals = vec![Vec3::ZERO; self.vertices.len()];
+        for tri in self.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| self.vertices[tri[k] as usize].position);
+            let normal = (b - a).cross(c - a);
+            tri.iter().for_each(|&i| face_normals[i as usize] += normal);
+        }
+        for (vertex, face_normal) in self.vertices.iter_mut().zip(face_normals) {
+            if vertex.normal == Vec3::ZERO {
+                vertex.normal = face_normal.normalize_or_zero();
+            }
+        }
+
+        let (min, max) = self.vertices.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), v| {
+            (min.min(v.position), max.max(v.position))
+        });
+        let center = 0.5 * (min + max);
+        let extent = (max - min).max_element();
+        let scale = if extent > 0.0 { BOID_LENGTH / extent } else { 1.0 };
+        let mirror = Vec3::new(-1.0, 1.0, 1.0);
+        for vertex in &mut self.vertices {
+            vertex.position = mirror * (vertex.position - center) * scale;
+            vertex.normal *= mirror;
+        }
+        Ok(self)
+    }
+
+    /// Adds a triangle with its own three vertices, all facing the way the
+    /// triangle does.
+    fn push_flat_triangle(&mut self, corners: [Vec3; 3], colors: [Vec4; 3]) {
+        let [a, b, c] = corners;
+        // counter-clockwise in a left-handed frame
+        let normal = (c - a).cross(b - a).normalize();
+        let base = self.vertices.len() as u32;
+        for (position, color) in corners.into_iter().zip(colors) {
+            self.vertices.push(Vertex { position, normal, color, uv: Vec2::ZERO });
+        }
+        self.indices.extend([base, base + 1, base + 2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io::BufReader};
+
+    use glam::Vec3;
+
+    use super::*;
+
+    fn positions(mesh: &Mesh) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        mesh.indices.chunks(3).map(|tri| [0, 1, 2].map(|k| mesh.vertices[tri[k] as usize].position))
+    }
+
+    fn center(mesh: &Mesh) -> Vec3 {
+        mesh.vertices.iter().fold(Vec3::ZERO, |sum, v| sum + v.position) / mesh.vertices.len() as f32
+    }
+
+    /// Checks that a convex mesh is wound counter-clockwise from outside in a
+    /// left-handed frame, with its normals pointing outwards.
+    fn assert_outward(mesh: &Mesh) {
+        let center = center(mesh);
+        for (tri, [a, b, c]) in mesh.indices.chunks(3).zip(positions(mesh)) {
+            let outwards = (a + b + c) / 3.0 - center;
+            // a face that's counter-clockwise from outside in a left-handed
+            // frame has its right-handed cross product pointing inwards
+            assert!((b - a).cross(c - a).dot(outwards) < 0.0, "{:?} is wound the wrong way", tri);
+            for &i in tri {
+                assert!(mesh.vertices[i as usize].normal.dot(outwards) > 0.0, "{:?} has an inward normal", tri);
+            }
+        }
+    }
+
+    fn load_obj(src: &str) -> Mesh {
+        let (models, _) = tobj::load_obj_buf(&mut BufReader::new(src.as_bytes()), &Mesh::obj_options(), |_| {
+            Err(tobj::LoadError::OpenFileFailed)
+        })
+        .unwrap();
+        Mesh::from_obj(&models, &[]).finish().unwrap()
+    }
+
+    // a right-handed unit tetrahedron, faces counter-clockwise from outside
+    const TETRAHEDRON: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 0 1 0
+        v 0 0 1
+        f 1 3 2
+        f 1 2 4
+        f 1 4 3
+        f 2 3 4
+    ";
+
+    #[test]
+    fn arrow_is_closed_and_consistently_wound() {
+        let mesh = Mesh::arrow();
+        assert_outward(&mesh);
+
+        // every edge is shared by exactly two faces, in opposite directions
+        let key = |v: Vec3| v.to_array().map(f32::to_bits);
+        let mut edges = HashSet::new();
+        for [a, b, c] in positions(&mesh) {
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                assert!(edges.insert((key(from), key(to))));
+            }
+        }
+        assert!(edges.iter().all(|&(from, to)| edges.contains(&(to, from))));
+    }
+
+    #[test]
+    fn arrow_points_down_z() {
+        let mesh = Mesh::arrow();
+        let tip = mesh.vertices.iter().map(|v| v.position.z).fold(f32::MIN, f32::max);
+        let tail = mesh.vertices.iter().map(|v| v.position.z).fold(f32::MAX, f32::min);
+        assert!(tip > 0.0 && tail < 0.0);
+        assert!(mesh.vertices.iter().filter(|v| v.position.z == tip).all(|v| v.position.truncate() == Vec2::ZERO));
+    }
+
+    #[test]
+    fn obj_is_fitted_mirrored_and_given_normals() {
+        let mesh = load_obj(TETRAHEDRON);
+        assert_eq!(mesh.indices.len(), 12);
+        assert_outward(&mesh);
+        assert!(mesh.vertices.iter().all(|v| v.color == DEFAULT_COLOR));
+
+        let (min, max) = mesh.vertices.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), v| {
+            (min.min(v.position), max.max(v.position))
+        });
+        assert!(min.abs_diff_eq(Vec3::splat(-0.5 * BOID_LENGTH), 1e-6));
+        assert!(max.abs_diff_eq(Vec3::splat(0.5 * BOID_LENGTH), 1e-6));
+    }
+
+    #[test]
+    fn obj_normals_and_colors_are_kept() {
+        let mesh = load_obj(
+            "
+            v 0 0 0 1 0 0
+            v 1 0 0 0 1 0
+            v 0 1 0 0 0 1
+            vn 0 0 1
+            vt 0.5 0.25
+            f 1/1/1 2/1/1 3/1/1
+            ",
+        );
+        assert_eq!(mesh.vertices.len(), 3);
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vec3::Z && v.uv == Vec2::new(0.5, 0.25)));
+        assert_eq!(mesh.vertices[0].color, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(mesh.vertices[2].color, Vec4::new(0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn gltf_triangles_are_loaded_with_node_transforms() {
+        // one triangle, no normals or indices, under a node that doubles it
+        // along X and gives it a red material
+        let positions: Vec<u8> = [0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let src = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{{ "mesh": 0, "scale": [2, 1, 1] }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "material": 0 }}] }}],
+                "materials": [{{ "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0, 0, 1] }} }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+                "buffers": [{{ "byteLength": 36, "uri": "data:application/octet-stream;base64,{}" }}]
+            }}"#,
+            base64(&positions)
+        );
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(src.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&document, None, blob).unwrap();
+        let mesh = Mesh::from_gltf(&document, &buffers);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.vertices[1].position, Vec3::new(2.0, 0.0, 0.0));
+        assert!(mesh.vertices.iter().all(|v| v.color == Vec4::new(1.0, 0.0, 0.0, 1.0)));
+
+        let mesh = mesh.finish().unwrap();
+        // counter-clockwise about +Z, mirrored across X
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vec3::Z));
+        assert!(mesh.vertices[1].position.x < mesh.vertices[0].position.x);
+    }
+
+    #[test]
+    fn gltf_indices_past_the_last_vertex_are_rejected() {
+        // a triangle whose last corner is a vertex that doesn't exist
+        let mut buffer: Vec<u8> = [0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        buffer.extend([0u16, 1, 7, 0].iter().flat_map(|i| i.to_le_bytes()));
+        let src = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+                "accessors": [
+                    {{
+                        "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                        "min": [0, 0, 0], "max": [1, 1, 0]
+                    }},
+                    {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+                ],
+                "buffers": [{{ "byteLength": 44, "uri": "data:application/octet-stream;base64,{}" }}]
+            }}"#,
+            base64(&buffer)
+        );
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(src.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&document, None, blob).unwrap();
+        let mesh = Mesh::from_gltf(&document, &buffers);
+        assert!(matches!(mesh.finish(), Err(MeshError::IndexOutOfRange { index: 7, len: 3 })));
+    }
+
+    #[test]
+    fn unusable_meshes_are_rejected() {
+        let empty = Mesh { vertices: Vec::new(), indices: Vec::new() };
+        assert!(matches!(empty.finish(), Err(MeshError::Empty)));
+        assert!(matches!(Mesh::load(Path::new("boid.stl")), Err(MeshError::UnknownFormat)));
+        assert!(matches!(Mesh::load(Path::new("does/not/exist.obj")), Err(MeshError::Obj(_))));
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,305 @@
+use std::path::Path;
+
+use glam::{Mat4, Vec3, Vec4};
+use image::RgbaImage;
+
+use crate::{coloring::Coloring, mesh::{Mesh, MeshError}, renderer::{create_depth_view, request_adapter, Lighting, RendererError, Scene}, world::World, Backend, BoidInstance};
+
+/// A texture the scene can be drawn into and read back from.
+pub(crate) struct Target {
+    texture: wgpu::Texture,
+    depth_view: wgpu::TextureView,
+    readback: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    /// Rows in `readback` are padded to wgpu's copy alignment.
+    padded_row_bytes: u64,
+}
+
+/// Renders worlds without a window, e.g. for figures, recordings and
+/// golden-image tests on machines with no display.
+pub struct OffscreenRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    scene: Scene,
+    target: Target,
+    view_proj: Mat4,
+    instance_data: Vec<BoidInstance>,
+    coloring: Coloring,
+    tints: Vec<Vec4>,
+}
+
+impl Target {
+    /// Fails rather than letting wgpu panic if the device can't make a
+    /// texture that big.
+    pub(crate) fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat)
+        -> Result<Self, RendererError> {
+        let max = device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            return Err(RendererError::TargetTooLarge { width, height, max });
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen target"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+        // not `div_ceil`, which is newer than the toolchains wgpu 0.12 targets
+        #[allow(clippy::manual_div_ceil)]
+        let padded_row_bytes = (4 * width as u64 + align - 1) / align * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen readback"),
+            size: padded_row_bytes * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            texture,
+            depth_view: create_depth_view(device, width, height),
+            readback,
+            width,
+            height,
+            format,
+            padded_row_bytes,
+        })
+    }
+
+    /// Clears the target, draws `scene` into it and reads the result back.
+    pub(crate) fn capture(&self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) -> RgbaImage {
+        let view = self.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("offscreen pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(Scene::depth_attachment(&self.depth_view)),
+        });
+        scene.draw(&mut render_pass);
+        drop(render_pass);
+
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.padded_row_bytes as u32),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapped).expect("could not map the readback buffer");
+
+        let row_bytes = 4 * self.width as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        for row in slice.get_mapped_range().chunks(self.padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+        self.readback.unmap();
+
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            pixels.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+        }
+        RgbaImage::from_raw(self.width, self.height, pixels).expect("readback has one pixel per texel")
+    }
+}
+
+impl OffscreenRenderer {
+    pub async fn new(width: u32, height: u32, backend: Backend) -> Result<Self, RendererError> {
+        let backends = backend.resolve();
+        let instance = wgpu::Instance::new(backends);
+        let adapter = request_adapter(&instance, backends, None).await?;
+        let info = adapter.get_info();
+        log::info!("rendering offscreen with {} ({:?}, {:?})", info.name, info.backend, info.device_type);
+
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+                label: None,
+            },
+            None,
+        ).await.map_err(RendererError::NoDevice)?;
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let scene = Scene::new(&device, format, &Mesh::arrow());
+        let target = Target::new(&device, width, height, format)?;
+
+        Ok(Self {
+            device,
+            queue,
+            scene,
+            target,
+            view_proj: Mat4::IDENTITY,
+            instance_data: Vec::new(),
+            coloring: Coloring::default(),
+            tints: Vec::new(),
+        })
+    }
+
+    /// Draws boids with the OBJ or glTF mesh at `path` instead of the
+    /// built-in dart.
+    pub fn load_mesh(&mut self, path: &Path) -> Result<(), MeshError> {
+        self.scene.set_mesh(&self.device, &Mesh::load(path)?);
+        Ok(())
+    }
+
+    pub fn set_lighting(&self, lighting: &Lighting) {
+        self.scene.write_lighting(&self.queue, lighting);
+    }
+
+    pub fn set_coloring(&mut self, coloring: Coloring) {
+        self.coloring = coloring;
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.target.width, self.target.height)
+    }
+
+    /// Points the camera from `eye` at `target`.
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3) {
+        let aspect_ratio = self.target.width as f32 / self.target.height as f32;
+        let proj = Mat4::perspective_lh(60f32.to_radians(), aspect_ratio, 0.01, 1000.0);
+        self.view_proj = proj * Mat4::look_at_lh(eye, target, Vec3::Y);
+    }
+
+    /// Places the camera slightly above and in front of `world`, far enough
+    /// back to see all of it.
+    pub fn frame(&mut self, world: &World) {
+        let side_len = world.side_len();
+        self.look_at(Vec3::new(0.0, 0.4 * side_len, -1.6 * side_len), Vec3::ZERO);
+    }
+
+    pub fn render(&mut self, world: &World) -> RgbaImage {
+        self.coloring.tints(world, &mut self.tints);
+        crate::fill_instances(world, &[], 0.0, &self.tints, &mut self.instance_data);
+        self.scene.write_camera(&self.queue, self.view_proj);
+        self.scene.write_instances(&self.device, &self.queue, &self.instance_data);
+        self.target.capture(&self.device, &self.queue, &self.scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use image::Rgba;
+
+    use super::OffscreenRenderer;
+    use crate::{renderer::RendererError, world::World, Backend};
+
+    /// Renders with whatever adapter there is, falling back to a software
+    /// one such as lavapipe or WARP. `None` only when there isn't even that,
+    /// in which case the calling test is skipped.
+    fn renderer(width: u32, height: u32) -> Option<OffscreenRenderer> {
+        match pollster::block_on(OffscreenRenderer::new(width, height, Backend::Auto)) {
+            Ok(renderer) => Some(renderer),
+            Err(e @ RendererError::NoAdapter { .. }) => {
+                eprintln!("skipping: {}", e);
+                None
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn it_renders_a_boid_onto_a_white_background() {
+        let mut renderer = match renderer(64, 48) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let mut world = World::new(10.0, 4);
+        world.add_boid(Vec3::ZERO);
+        renderer.look_at(Vec3::new(0.0, 0.0, -1.0), Vec3::ZERO);
+
+        let image = renderer.render(&world);
+
+        assert_eq!(image.dimensions(), (64, 48));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert!(image.pixels().any(|px| *px != Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn every_boid_is_drawn() {
+        let mut renderer = match renderer(64, 32) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let mut world = World::new(10.0, 4);
+        renderer.look_at(Vec3::new(0.0, 0.0, -2.0), Vec3::ZERO);
+
+        world.add_boid(Vec3::new(-1.0, 0.0, 0.0));
+        let one = renderer.render(&world);
+        world.add_boid(Vec3::new(1.0, 0.0, 0.0));
+        world.add_boid(Vec3::new(0.0, 0.8, 0.0));
+        let three = renderer.render(&world);
+
+        let covered = |image: &image::RgbaImage| image.pixels().filter(|px| **px != Rgba([255, 255, 255, 255])).count();
+        assert!(covered(&three) > 2 * covered(&one));
+    }
+
+    #[test]
+    fn an_empty_world_renders_blank() {
+        let mut renderer = match renderer(32, 32) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let world = World::new(10.0, 4);
+        renderer.frame(&world);
+
+        let image = renderer.render(&world);
+
+        assert!(image.pixels().all(|px| *px == Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn rows_are_unpadded_and_survive_a_png_round_trip() {
+        // 4 * 50 bytes a row, padded to 256 in the readback buffer
+        let mut renderer = match renderer(50, 20) {
+            Some(renderer) => renderer,
+            None => return,
+        };
+        let mut world = World::new(10.0, 4);
+        world.add_boid(Vec3::ZERO);
+        renderer.look_at(Vec3::new(0.0, 0.0, -1.0), Vec3::ZERO);
+        let image = renderer.render(&world);
+
+        let path = std::env::temp_dir().join(format!("iridium-offscreen-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let loaded = image::open(&path).unwrap().into_rgba8();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(image.dimensions(), (50, 20));
+        assert_eq!(image.as_raw().len(), 4 * 50 * 20);
+        assert_eq!(*image.get_pixel(49, 19), Rgba([255, 255, 255, 255]));
+        assert_eq!(loaded, image);
+    }
+
+    #[test]
+    fn targets_bigger_than_the_device_allows_are_an_error() {
+        if renderer(1, 1).is_none() {
+            return;
+        }
+        let result = pollster::block_on(OffscreenRenderer::new(u32::MAX, 1, Backend::Auto));
+        assert!(matches!(result, Err(RendererError::TargetTooLarge { width: u32::MAX, height: 1, .. })));
+    }
+}
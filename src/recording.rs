@@ -0,0 +1,121 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use serde::Serialize;
+
+use crate::{offscreen::OffscreenRenderer, world::World};
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Writes a run out as numbered PNG frames plus a JSON manifest describing
+/// them, ready to be turned into a video with e.g.
+/// `ffmpeg -framerate <fps> -i frame_%06d.png out.mp4`.
+///
+/// Frames are taken per simulation tick rather than per unit of wall-clock
+/// time, so the result plays back smoothly however slow rendering is.
+pub struct Recorder {
+    dir: PathBuf,
+    every: u64,
+    renderer: OffscreenRenderer,
+    manifest: Manifest,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    width: u32,
+    height: u32,
+    /// Playback rate at which the video runs in simulation real time.
+    fps: f64,
+    tick_rate: f64,
+    ticks_per_frame: u64,
+    seed: u64,
+    pattern: &'static str,
+    frames: Vec<Frame>,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    tick: u64,
+    file: String,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Image(image::ImageError),
+}
+
+impl Recorder {
+    /// Records into `dir`, creating it if needed, capturing every `every`th
+    /// tick of a simulation running at `tick_rate` ticks per second.
+    pub fn new(dir: impl AsRef<Path>, every: u64, tick_rate: f64, renderer: OffscreenRenderer, world: &World)
+        -> Result<Self, RecordingError> {
+        assert!(every > 0, "must record at least every tick");
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (width, height) = renderer.size();
+
+        Ok(Self {
+            dir,
+            every,
+            renderer,
+            manifest: Manifest {
+                width,
+                height,
+                fps: tick_rate / every as f64,
+                tick_rate,
+                ticks_per_frame: every,
+                seed: world.seed(),
+                pattern: "frame_%06d.png",
+                frames: Vec::new(),
+            },
+        })
+    }
+
+    /// Captures `world` if its tick is one being recorded.
+    pub fn record(&mut self, world: &World) -> Result<(), RecordingError> {
+        // not `is_multiple_of`, which is newer than the toolchains wgpu 0.12 targets
+        #[allow(clippy::manual_is_multiple_of)]
+        if world.tick() % self.every != 0 {
+            return Ok(());
+        }
+        let file = format!("frame_{:06}.png", self.manifest.frames.len());
+        self.renderer.render(world).save(self.dir.join(&file))?;
+        self.manifest.frames.push(Frame { tick: world.tick(), file });
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.manifest.frames.len()
+    }
+
+    /// Writes the manifest, returning where it went.
+    pub fn finish(self) -> Result<PathBuf, RecordingError> {
+        let path = self.dir.join(MANIFEST_NAME);
+        let json = serde_json::to_string_pretty(&self.manifest).map_err(io::Error::from)?;
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "could not write recording: {}", e),
+            RecordingError::Image(e) => write!(f, "could not write frame: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    fn from(e: io::Error) -> Self {
+        RecordingError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for RecordingError {
+    fn from(e: image::ImageError) -> Self {
+        RecordingError::Image(e)
+    }
+}
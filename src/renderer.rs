@@ -1,21 +1,90 @@
-use std::{rc::Rc, time::{Instant, Duration}};
+use std::{fmt, path::PathBuf, rc::Rc, time::{Instant, Duration, SystemTime}};
 
-use crate::{camera::{Camera, Movement}, BoidInstance};
-use glam::{Vec3, Mat4, Vec4};
+use crate::{camera::{Camera, CameraMode, OrbitTarget, Projection, View}, imgui::CameraControls, input::{Bindings, InputState}, mesh::{Mesh, MeshError, Vertex}, offscreen::Target, Backend, BoidInstance, Config, Vsync};
+use glam::{Vec3, Vec4, Mat4};
 use wgpu::{include_wgsl, util::DeviceExt, BufferDescriptor, BufferAddress};
-use winit::{window::Window, event::{WindowEvent, Event, KeyboardInput, ElementState, VirtualKeyCode}};
+use winit::{window::Window, event::{DeviceEvent, WindowEvent, Event, ElementState}};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: Vec4,
-    color: Vec4,
+struct MatrixData {
+    view_proj: Mat4,
+}
+
+/// A single directional light plus a flat ambient term.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lighting {
+    /// The way the light shines, from the light towards the scene.
+    pub direction: Vec3,
+    pub color: Vec3,
+    /// Added to every surface whichever way it faces, so that the side of a
+    /// boid facing away from the light isn't black.
+    pub ambient: Vec3,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(0.3, -1.0, 0.4).normalize(),
+            color: Vec3::splat(0.8),
+            ambient: Vec3::splat(0.3),
+        }
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct MatrixData {
-    mvp: Mat4,
+struct LightingData {
+    direction: Vec4,
+    color: Vec4,
+    ambient: Vec4,
+}
+
+impl From<&Lighting> for LightingData {
+    fn from(lighting: &Lighting) -> Self {
+        let direction = lighting.direction.try_normalize().unwrap_or(-Vec3::Y);
+        Self {
+            direction: direction.extend(0.0),
+            color: lighting.color.extend(0.0),
+            ambient: lighting.ambient.extend(0.0),
+        }
+    }
+}
+
+/// The depth buffer format for every pass that draws the scene.
+pub(crate) const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+#[derive(Debug)]
+pub enum RendererError {
+    /// No adapter on the requested backends could present to the window,
+    /// not even a fallback one. Lists every adapter that was considered.
+    NoAdapter { backends: wgpu::Backends, tried: Vec<wgpu::AdapterInfo> },
+    NoDevice(wgpu::RequestDeviceError),
+    /// The adapter opened fine but has no format it can present to the
+    /// window in.
+    UnsupportedSurface(wgpu::AdapterInfo),
+    /// The boid mesh named in the config couldn't be loaded.
+    Mesh(PathBuf, MeshError),
+    /// An offscreen target was asked for with a side longer than the
+    /// device's largest texture.
+    TargetTooLarge { width: u32, height: u32, max: u32 },
+}
+
+/// The GPU resources for drawing the flock, independent of where it's
+/// drawn to.
+pub(crate) struct Scene {
+    render_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    instance_buffer: wgpu::Buffer,
+    /// How many instances `instance_buffer` has room for.
+    instance_capacity: usize,
+    /// How many of those are in use this frame.
+    instance_count: usize,
+    matrix_data: wgpu::Buffer,
+    lighting_data: wgpu::Buffer,
+    matrix_bind_group: wgpu::BindGroup,
 }
 
 pub struct Renderer {
@@ -24,27 +93,33 @@ pub struct Renderer {
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    instance_buffer: wgpu::Buffer,
+    scene: Scene,
+    /// Recreated along with the surface whenever the window is resized.
+    depth_view: wgpu::TextureView,
     imgui_renderer: crate::imgui::Imgui,
     camera: Camera,
-    matrix_data: wgpu::Buffer,
-    matrix_bind_group: wgpu::BindGroup,
+    input: InputState,
+    bindings: Bindings,
+    /// Set while the mouse is steering the camera, with the cursor grabbed.
+    mouse_look: bool,
+    orbit_target: OrbitTarget,
+    /// The boid orbited when cycling through targets gets to one.
+    selected_boid: usize,
+    window: Rc<Window>,
     time: Instant,
+    screenshot_dir: PathBuf,
+    screenshot_requested: bool,
 }
 
 impl Renderer {
-    pub async fn new(window: Rc<Window>) -> Self {
+    pub async fn new(window: Rc<Window>, app_config: &Config) -> Result<Self, RendererError> {
         let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::Backends::METAL);
+        let backends = app_config.backend.resolve();
+        let instance = wgpu::Instance::new(backends);
         let surface = unsafe { instance.create_surface(&window.as_ref()) };
-        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }).await.expect("could not find an adequate adapter");
+        let adapter = request_adapter(&instance, backends, Some(&surface)).await?;
+        let info = adapter.get_info();
+        log::info!("rendering with {} ({:?}, {:?})", info.name, info.backend, info.device_type);
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -53,17 +128,333 @@ impl Renderer {
                 label: None,
             },
             None,
-        ).await.expect("could not find a suitable device");
+        ).await.map_err(RendererError::NoDevice)?;
 
+        let format = surface.get_preferred_format(&adapter).ok_or(RendererError::UnsupportedSurface(info))?;
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Immediate,
+            present_mode: match app_config.vsync {
+                Vsync::Off => wgpu::PresentMode::Immediate,
+                Vsync::On => wgpu::PresentMode::Fifo,
+                Vsync::Mailbox => wgpu::PresentMode::Mailbox,
+            },
         };
         surface.configure(&device, &config);
 
+        let mut camera = Camera::new(
+            Vec3::new(0.0, 0.0, -0.5),
+            Vec3::ZERO,
+            90.0,
+            0.01,
+            100.0,
+            config.width as f32 / config.height as f32
+        );
+
+        camera.set_chase_offset(app_config.chase_offset);
+        camera.set_mode(app_config.camera_mode);
+        let selected_boid = app_config.follow.unwrap_or(0);
+        let orbit_target = match app_config.follow {
+            Some(i) => OrbitTarget::Boid(i),
+            None => OrbitTarget::WorldCenter,
+        };
+
+        let mesh = match &app_config.mesh {
+            Some(path) => Mesh::load(path).map_err(|e| RendererError::Mesh(path.clone(), e))?,
+            None => Mesh::arrow(),
+        };
+        let scene = Scene::new(&device, config.format, &mesh);
+        scene.write_lighting(&queue, &app_config.lighting);
+        let depth_view = create_depth_view(&device, config.width, config.height);
+        let imgui_renderer = crate::imgui::Imgui::new(window.clone(), &device, &queue, &config);
+        
+        Ok(Self {
+            device,
+            surface,
+            queue,
+            config,
+            size,
+            depth_view,
+            scene,
+            imgui_renderer,
+            camera,
+            input: InputState::default(),
+            bindings: app_config.bindings.clone(),
+            mouse_look: false,
+            orbit_target,
+            selected_boid,
+            window,
+            time: Instant::now(),
+            screenshot_dir: app_config.screenshot_dir.clone(),
+            screenshot_requested: false,
+        })
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.depth_view = create_depth_view(&self.device, new_size.width, new_size.height);
+            self.camera.set_aspect_ratio(new_size.width as f32, new_size.height as f32);
+        }
+    }
+
+    /// Records keyboard and mouse input for the next frame, returning
+    /// whether the event was bound to something.
+    pub fn input(&mut self, win_event: &WindowEvent, event: &Event<()>) -> bool {
+        self.imgui_renderer.event(event);
+        // presses over the UI are the UI's, but releases always count so
+        // nothing is left held down
+        let captured = match win_event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                input.state == ElementState::Pressed && self.imgui_renderer.wants_keyboard()
+            }
+            WindowEvent::MouseInput { state, .. } => {
+                *state == ElementState::Pressed && self.imgui_renderer.wants_mouse()
+            }
+            WindowEvent::MouseWheel { .. } => self.imgui_renderer.wants_mouse(),
+            _ => false,
+        };
+        if captured {
+            return false;
+        }
+        matches!(self.input.handle_window_event(win_event), Some(binding) if self.bindings.is_bound(binding))
+    }
+
+    pub fn device_input(&mut self, event: &DeviceEvent) {
+        self.input.handle_device_event(event);
+    }
+
+    /// Applies this frame's input to the camera, once per frame so that
+    /// held keys move it at the same speed whatever the frame rate.
+    fn apply_input(&mut self, delta_t: Duration) {
+        // each of these switches to its mode, or back to flying from it
+        let toggles = [
+            (&self.bindings.toggle_orbit, CameraMode::Orbit),
+            (&self.bindings.chase, CameraMode::Chase),
+            (&self.bindings.first_person, CameraMode::FirstPerson),
+        ];
+        let toggled = toggles.into_iter().find(|(action, _)| self.input.any_pressed(action)).map(|(_, mode)| mode);
+        if let Some(mode) = toggled {
+            self.set_camera_mode(if self.camera.mode() == mode { CameraMode::Fly } else { mode });
+        }
+        if self.input.any_pressed(&self.bindings.orbit_target) {
+            self.set_orbit_target(match self.orbit_target {
+                OrbitTarget::WorldCenter => OrbitTarget::Centroid,
+                OrbitTarget::Centroid => OrbitTarget::Boid(self.selected_boid),
+                OrbitTarget::Boid(_) => OrbitTarget::WorldCenter,
+            });
+        }
+        let views = [
+            (&self.bindings.top_view, View::Top),
+            (&self.bindings.front_view, View::Front),
+            (&self.bindings.side_view, View::Side),
+        ];
+        let view = views.into_iter().find(|(action, _)| self.input.any_pressed(action)).map(|(_, view)| view);
+        if let Some(view) = view {
+            self.set_view(view);
+        }
+        if self.input.any_pressed(&self.bindings.toggle_projection) {
+            self.camera.set_projection(match self.camera.lens().projection {
+                Projection::Perspective => Projection::Orthographic,
+                Projection::Orthographic => Projection::Perspective,
+            });
+        }
+        match self.camera.mode() {
+            CameraMode::Fly => {
+                self.set_mouse_look(self.input.any_down(&self.bindings.mouse_look));
+                if self.mouse_look {
+                    self.camera.add_look(self.input.mouse_delta());
+                }
+                // flying closer doesn't make an orthographic view any bigger
+                match self.camera.lens().projection {
+                    Projection::Perspective => self.camera.add_speed_steps(self.input.scroll()),
+                    Projection::Orthographic => self.camera.add_zoom_steps(self.input.scroll()),
+                }
+            }
+            CameraMode::Orbit => {
+                self.set_mouse_look(self.input.any_down(&self.bindings.orbit));
+                if self.mouse_look && self.input.any_down(&self.bindings.pan) {
+                    self.camera.add_pan(self.input.mouse_delta());
+                } else if self.mouse_look {
+                    self.camera.add_orbit(self.input.mouse_delta());
+                }
+                self.camera.add_zoom_steps(self.input.scroll());
+            }
+            CameraMode::Chase | CameraMode::FirstPerson => self.set_mouse_look(false),
+        }
+        self.camera.add_movement(&self.bindings.movement(&self.input), delta_t);
+        if self.input.any_pressed(&self.bindings.screenshot) {
+            self.screenshot_requested = true;
+        }
+        self.input.end_frame();
+    }
+
+    fn set_mouse_look(&mut self, on: bool) {
+        if on == self.mouse_look {
+            return;
+        }
+        self.mouse_look = on;
+        if let Err(e) = self.window.set_cursor_grab(on) {
+            log::warn!("could not {} the cursor: {}", if on { "grab" } else { "release" }, e);
+        }
+        self.window.set_cursor_visible(!on);
+    }
+
+    /// Switches between flying and orbiting. An orbit starts out centred on
+    /// the orbit target.
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera.set_mode(mode);
+        self.camera.reset_pan();
+    }
+
+    /// Looks straight along one of the world's axes, orthographically.
+    fn set_view(&mut self, view: View) {
+        self.camera.set_view(view);
+        self.camera.set_projection(Projection::Orthographic);
+    }
+
+    /// Selects the boid with index `i` to orbit, chase or ride along on.
+    pub fn select_boid(&mut self, i: usize) {
+        self.selected_boid = i;
+        if let OrbitTarget::Boid(_) = self.orbit_target {
+            self.set_orbit_target(OrbitTarget::Boid(i));
+        }
+    }
+
+    pub fn selected_boid(&self) -> usize {
+        self.selected_boid
+    }
+
+    /// The ray under the cursor, as origin and direction, if a boid was
+    /// clicked on since the last frame.
+    pub(crate) fn pick_ray(&self) -> Option<(Vec3, Vec3)> {
+        let size = glam::Vec2::new(self.config.width as f32, self.config.height as f32);
+        self.input.clicked(&self.bindings.select).map(|cursor| self.camera.ray(cursor, size))
+    }
+
+    /// Tells the camera where the selected boid is this frame.
+    pub(crate) fn set_follow_pose(&mut self, position: Vec3, rotation: glam::Quat) {
+        self.camera.set_follow_pose(position, rotation);
+    }
+
+    /// Orbits `target` from now on, re-centring the orbit on it.
+    pub fn set_orbit_target(&mut self, target: OrbitTarget) {
+        if let OrbitTarget::Boid(i) = target {
+            self.selected_boid = i;
+        }
+        self.orbit_target = target;
+        self.camera.reset_pan();
+    }
+
+    pub fn orbit_target(&self) -> OrbitTarget {
+        self.orbit_target
+    }
+
+    /// Tells the camera where the orbit target is this frame.
+    pub(crate) fn set_orbit_point(&mut self, point: Vec3) {
+        self.camera.set_target_point(point);
+    }
+
+    pub(crate) fn fill_instance_buffer(&mut self, instance_data: &[BoidInstance]) {
+        self.scene.write_instances(&self.device, &self.queue, instance_data);
+    }
+
+    pub fn render(&mut self, delta_t: Duration) -> Result<(), wgpu::SurfaceError> {
+        self.apply_input(delta_t);
+        self.camera.update(delta_t);
+        let swapchain_image = self.surface.get_current_texture()?;
+        let swapchain_imageview = swapchain_image.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let color_attachment = wgpu::RenderPassColorAttachment {
+            view: &swapchain_imageview,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                store: true,
+            },
+        };
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        self.scene.write_camera(&self.queue, self.camera.model_view_proj(Mat4::IDENTITY));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("main pass"),
+            color_attachments: &[color_attachment],
+            depth_stencil_attachment: Some(Scene::depth_attachment(&self.depth_view)),
+        });
+        self.scene.draw(&mut render_pass);
+        drop(render_pass);
+
+        // the UI gets a pass of its own with no depth attachment, so it's
+        // always drawn over the boids
+        let mut ui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ui pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &swapchain_imageview,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let mut controls = CameraControls::new(self.camera.mode(), self.camera.lens());
+        self.imgui_renderer.render_ui(&self.device, &self.queue, &mut ui_pass, &mut controls);
+        drop(ui_pass);
+        // takes effect from the next frame, as input does
+        if controls.mode != self.camera.mode() {
+            self.set_camera_mode(controls.mode);
+        }
+        self.camera.set_lens(controls.lens);
+        if let Some(view) = controls.view {
+            self.set_view(view);
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        swapchain_image.present();
+
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            self.save_screenshot();
+        }
+        Ok(())
+    }
+
+    /// Draws the scene again, without the UI, into an offscreen texture the
+    /// size of the window and writes it to a timestamped PNG.
+    fn save_screenshot(&self) {
+        let target = match Target::new(&self.device, self.config.width, self.config.height, self.config.format) {
+            Ok(target) => target,
+            Err(e) => {
+                log::error!("could not take a screenshot: {}", e);
+                return;
+            }
+        };
+        let image = target.capture(&self.device, &self.queue, &self.scene);
+
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let name = format!("iridium-{}{:03}.png", since_epoch.as_secs(), since_epoch.subsec_millis());
+        let path = self.screenshot_dir.join(name);
+        match image.save(&path) {
+            Ok(()) => log::info!("saved screenshot to {}", path.display()),
+            Err(e) => log::error!("could not save screenshot to {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn set_lighting(&self, lighting: &Lighting) {
+        self.scene.write_lighting(&self.queue, lighting);
+    }
+
+    pub(crate) fn camera(&self) -> &Camera {
+        &self.camera
+    }
+}
+
+impl Scene {
+    pub(crate) fn new(device: &wgpu::Device, format: wgpu::TextureFormat, mesh: &Mesh) -> Self {
         let vert_shader = device.create_shader_module(&include_wgsl!("shaders/vert.wgsl"));
         let frag_shader = device.create_shader_module(&include_wgsl!("shaders/frag.wgsl"));
 
@@ -71,8 +462,10 @@ impl Renderer {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
-                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 0, shader_location: 0 },
-                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 16, shader_location: 1 },
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 0, shader_location: 2 },
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x3, offset: 16, shader_location: 0 },
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x3, offset: 28, shader_location: 1 },
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x2, offset: 40, shader_location: 3 },
             ],
         };
 
@@ -80,47 +473,26 @@ impl Renderer {
             array_stride: std::mem::size_of::<BoidInstance>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Instance,
             attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: 16 as wgpu::BufferAddress,
-                    shader_location: 3,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: 32 as wgpu::BufferAddress,
-                    shader_location: 4,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: 48 as wgpu::BufferAddress,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
+                // position and scale
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 0, shader_location: 4 },
+                // rotation quaternion
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 16, shader_location: 5 },
+                // tint
+                wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: 32, shader_location: 6 },
             ]
         };
         
-        let camera = Camera::new(
-            Vec3::new(0.0, 0.0, 0.5),
-            Vec3::ZERO,
-            90.0,
-            0.01,
-            100.0,
-            config.width as f32 / config.height as f32
-        );
-
-        // let halve_size = Mat4::from_scale(Vec3::new(1.5, 1.5, 1.5));
-        let halve_size = Mat4::IDENTITY;
-        // let camera_uniform = UniformData { mvp: halve_size.to_cols_array_2d() };
-        let camera_uniform = MatrixData { mvp: camera.model_view_proj(halve_size) };
-
         let uniform_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("uniform buff"),
-                contents: bytemuck::cast_slice(&[camera_uniform]),
+                contents: bytemuck::cast_slice(&[MatrixData { view_proj: Mat4::IDENTITY }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        let lighting_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("lighting buff"),
+                contents: bytemuck::cast_slice(&[LightingData::from(&Lighting::default())]),
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             }
         );
@@ -133,6 +505,12 @@ impl Renderer {
                     ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                    count: None,
+                },
             ],
             label: Some("stuff"),
         });
@@ -143,7 +521,11 @@ impl Renderer {
                 wgpu::BindGroupEntry {
                     binding: 0,
                     resource: uniform_buffer.as_entire_binding(),
-                }
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lighting_buffer.as_entire_binding(),
+                },
             ],
             label: Some("other stuff"),
         });
@@ -157,7 +539,8 @@ impl Renderer {
         );
 
         let render_pipeline = Self::create_render_pipeline(
-            &device,
+            device,
+            format,
             vertex_layout,
             Some(instance_layout),
             &vert_shader,
@@ -165,166 +548,114 @@ impl Renderer {
             render_pipeline_layout,
         );
 
-        let vertices: &[Vertex] = &[
-            Vertex { position: Vec4::new(0.0868241, 0.49240386, 0.0, 0.0), color: Vec4::new(0.5, 0.0, 0.5, 0.0) }, // A
-            Vertex { position: Vec4::new(-0.49513406, 0.06958647, 0.0, 0.0), color: Vec4::new(0.5, 0.0, 0.5, 0.0) }, // B
-            Vertex { position: Vec4::new(0.21918549, -0.44939706, 0.0, 0.0), color: Vec4::new(0.5, 0.0, 0.5, 0.0) }, // C
-        ];
-        let indices: &[u16] = &[
-            0, 1, 2,
-        ];
+        let (vertex_buffer, index_buffer) = Self::create_mesh_buffers(device, mesh);
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buff"),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buff"),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        
-        let instance_buffer = device.create_buffer(&BufferDescriptor { 
-            label: Some("hello"), 
-            size: std::mem::size_of::<BoidInstance>() as BufferAddress, 
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-         });
+        let instance_capacity = 1;
+        let instance_buffer = Self::create_instance_buffer(device, instance_capacity);
 
-        let imgui_renderer = crate::imgui::Imgui::new(window.clone(), &device, &queue, &config);
-        
         Self {
-            device,
-            surface,
-            queue,
-            config,
-            size,
             render_pipeline,
             vertex_buffer,
-            instance_buffer,
             index_buffer,
-            imgui_renderer,
-            camera,
+            index_count: mesh.indices.len() as u32,
+            instance_buffer,
+            instance_capacity,
+            instance_count: 0,
             matrix_data: uniform_buffer,
+            lighting_data: lighting_buffer,
             matrix_bind_group: uniform_bind_group,
-            time: Instant::now(),
         }
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-        }
+    /// Draws boids with `mesh` from now on.
+    pub(crate) fn set_mesh(&mut self, device: &wgpu::Device, mesh: &Mesh) {
+        (self.vertex_buffer, self.index_buffer) = Self::create_mesh_buffers(device, mesh);
+        self.index_count = mesh.indices.len() as u32;
     }
 
-    pub fn input(&mut self, _win_event: &WindowEvent, _event: &Event<()>) -> bool {
-        let mut movement = Movement::default();
-        
-        match _win_event {
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state,
-                    virtual_keycode: Some(keycode),
-                    ..
-                },
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    VirtualKeyCode::W => {
-                        movement.strafe_forward = true;
-                    },
-                    VirtualKeyCode::S => {
-                        movement.strafe_back = true;
-                    },
-                    VirtualKeyCode::A => {
-                        movement.strafe_left = true;
-                    },
-                    VirtualKeyCode::D => {
-                        movement.strafe_right = true;
-                    },
-                    _ => (),
-                }
-            }
-            
-            _ => ()
-            
-        };
-        self.camera.add_movement(movement);
-        self.imgui_renderer.event(_event);
-        false
+    fn create_mesh_buffers(device: &wgpu::Device, mesh: &Mesh) -> (wgpu::Buffer, wgpu::Buffer) {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buff"),
+            contents: bytemuck::cast_slice(&mesh.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buff"),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        (vertex_buffer, index_buffer)
     }
 
-    pub(crate) fn fill_instance_buffer(&mut self, instance_data: &[BoidInstance]) {
-        self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instance_data));
-        self.queue.submit(std::iter::empty());
+    pub(crate) fn write_camera(&self, queue: &wgpu::Queue, view_proj: Mat4) {
+        queue.write_buffer(&self.matrix_data, 0, bytemuck::cast_slice(&[MatrixData { view_proj }]));
     }
 
-    pub fn render(&mut self, delta_t: Duration) -> Result<(), wgpu::SurfaceError> {
-        self.camera.update(delta_t);
-        let swapchain_image = self.surface.get_current_texture()?;
-        let swapchain_imageview = swapchain_image.texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let color_attachment = wgpu::RenderPassColorAttachment {
-            view: &swapchain_imageview,
-            resolve_target: None,
-            ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
-                store: true,
-            },
-        };
+    pub(crate) fn write_lighting(&self, queue: &wgpu::Queue, lighting: &Lighting) {
+        queue.write_buffer(&self.lighting_data, 0, bytemuck::cast_slice(&[LightingData::from(lighting)]));
+    }
 
-        let mvp = MatrixData {
-            mvp: self.camera.model_view_proj(Mat4::IDENTITY),
-        };
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        self.queue.write_buffer(&self.matrix_data, 0, bytemuck::cast_slice(&[mvp]));
+    /// Uploads this frame's instances, reallocating the instance buffer at
+    /// (at least) double its size if they don't fit.
+    pub(crate) fn write_instances(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instance_data: &[BoidInstance]) {
+        if instance_data.len() > self.instance_capacity {
+            self.instance_capacity = instance_data.len().max(2 * self.instance_capacity);
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        self.instance_count = instance_data.len();
+        if !instance_data.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instance_data));
+        }
+    }
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("main pass"),
-            color_attachments: &[color_attachment],
-            depth_stencil_attachment: None,
-        });
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("instance buffer"),
+            size: (capacity * std::mem::size_of::<BoidInstance>()) as BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
 
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, &self.matrix_bind_group, &[]);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.draw_indexed(0..3, 0, 0..1);
-        // render_pass.draw(0..3, 0..1);
-
-        self.imgui_renderer.render_ui(&self.device, &self.queue, &mut render_pass);
-        drop(render_pass);
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..self.instance_count as u32);
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        swapchain_image.present();
-        Ok(())
+    /// Clears `view` to the far plane at the start of a pass.
+    pub(crate) fn depth_attachment(view: &wgpu::TextureView) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+        wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }
     }
 
-    fn create_render_pipeline(device: &wgpu::Device, vert_layout: wgpu::VertexBufferLayout, inst_layout: Option<wgpu::VertexBufferLayout>, vert: &wgpu::ShaderModule, 
+    fn create_render_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, vert_layout: wgpu::VertexBufferLayout, inst_layout: Option<wgpu::VertexBufferLayout>, vert: &wgpu::ShaderModule, 
         frag: &wgpu::ShaderModule, layout: wgpu::PipelineLayout) -> wgpu::RenderPipeline {
             
         let buff = if let Some(layout) = inst_layout { vec![vert_layout, layout] } else { vec![vert_layout] };
-        let render_pipeline = device.create_render_pipeline(
+        device.create_render_pipeline(
             &wgpu::RenderPipelineDescriptor {
                 label: Some("graphics pipeline"),
                 layout: Some(&layout),
                 vertex: wgpu::VertexState {
-                    module: &vert,
+                    module: vert,
                     entry_point: "main",
                     buffers: &buff,
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &frag,
+                    module: frag,
                     entry_point: "main",
                     targets: &[wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                        format,
                         blend: Some(wgpu::BlendState::REPLACE),
                         write_mask: wgpu::ColorWrites::ALL,
                     }],
@@ -338,7 +669,13 @@ impl Renderer {
                     unclipped_depth: false,
                     conservative: false,
                 },
-                depth_stencil: None,
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
                 multisample: wgpu::MultisampleState {
                     count: 1,
                     mask: !0,
@@ -346,12 +683,100 @@ impl Renderer {
                 },
                 multiview: None,
             }
-        );
-        render_pipeline
+        )
     }
+}
 
+/// A depth buffer to draw a `width` by `height` target with.
+pub(crate) fn create_depth_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth buffer"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
 
-    pub(crate) fn camera(&self) -> &Camera {
-        &self.camera
+/// Picks the best hardware adapter on `backends`, able to present to
+/// `surface` if there is one, falling back to a software adapter if no
+/// hardware one fits.
+pub(crate) async fn request_adapter(
+    instance: &wgpu::Instance,
+    backends: wgpu::Backends,
+    surface: Option<&wgpu::Surface>,
+) -> Result<wgpu::Adapter, RendererError> {
+    for force_fallback_adapter in [false, true] {
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter,
+            })
+            .await;
+        if let Some(adapter) = adapter {
+            if force_fallback_adapter {
+                log::warn!("no hardware adapter found, using the fallback adapter");
+            }
+            return Ok(adapter);
+        }
+    }
+
+    let tried = instance
+        .enumerate_adapters(backends)
+        .map(|a| a.get_info())
+        .collect();
+    Err(RendererError::NoAdapter { backends, tried })
+}
+
+impl Backend {
+    /// The backends to try: the configured one, or for `Auto` whatever
+    /// `WGPU_BACKEND` names, defaulting to every primary backend.
+    pub(crate) fn resolve(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto => wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Metal => wgpu::Backends::METAL,
+        }
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::NoAdapter { backends, tried } if tried.is_empty() => {
+                write!(f, "no graphics adapters found for backends {:?}", backends)
+            }
+            RendererError::NoAdapter { backends, tried } => {
+                write!(
+                    f,
+                    "none of the adapters for backends {:?} are usable:",
+                    backends
+                )?;
+                for info in tried {
+                    write!(
+                        f,
+                        "\n  {} ({:?}, {:?})",
+                        info.name, info.backend, info.device_type
+                    )?;
+                }
+                Ok(())
+            }
+            RendererError::NoDevice(e) => write!(f, "could not open the graphics device: {}", e),
+            RendererError::UnsupportedSurface(info) => {
+                write!(f, "{} ({:?}) cannot present to the window in any format", info.name, info.backend)
+            }
+            RendererError::Mesh(path, e) => write!(f, "could not load boid mesh {}: {}", path.display(), e),
+            RendererError::TargetTooLarge { width, height, max } => {
+                write!(f, "cannot render {}x{} offscreen, the largest this device can do is {}x{}", width, height, max, max)
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+impl std::error::Error for RendererError {}
@@ -0,0 +1,369 @@
+use std::{f32::consts::TAU, fmt, fs, io, path::Path};
+
+use glam::Vec3;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::world::{Boundary, InvalidSetting, Steering, World, DEFAULT_SEED};
+
+/// Declarative description of a world and the flocks it starts with, read
+/// from RON files. Everything except `flocks` can be left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub side_len: f32,
+    pub cells_per_side: usize,
+    pub boundary: Boundary,
+    pub seed: Option<u64>,
+    pub steering: Steering,
+    pub flocks: Vec<Flock>,
+}
+
+/// A group of boids spawned together.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Flock {
+    /// Ignored for `Spawn::Explicit`, which spawns one boid per position.
+    #[serde(default)]
+    pub count: usize,
+    pub spawn: Spawn,
+    #[serde(default)]
+    pub velocity: Velocity,
+}
+
+/// Where a flock's boids start.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Spawn {
+    UniformBox { min: Vec3, max: Vec3 },
+    SphereShell { center: Vec3, inner_radius: f32, outer_radius: f32 },
+    Gaussian { center: Vec3, std_dev: f32 },
+    Explicit(Vec<Vec3>),
+}
+
+/// How fast and which way a flock's boids start out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Velocity {
+    Zero,
+    Constant(Vec3),
+    /// A random direction at a speed drawn uniformly from the range.
+    Random { min_speed: f32, max_speed: f32 },
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(ron::Error),
+    /// The world itself can't be built.
+    World(InvalidSetting),
+    /// The flock at this index spawns its boids nowhere sensible.
+    Flock(usize, &'static str),
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        Self::from_ron(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, ScenarioError> {
+        Ok(ron::de::from_str(source)?)
+    }
+
+    /// Checks everything `build` relies on: a world with a positive size
+    /// and a sane number of cells, non-negative steering radii, finite
+    /// weights, a boundary that steers inwards and flocks with real,
+    /// correctly ordered bounds. Loading doesn't check, so that
+    /// overrides can be applied first.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        World::check_settings(self.side_len, self.cells_per_side, &self.steering, &self.boundary)
+            .map_err(ScenarioError::World)?;
+        for (i, flock) in self.flocks.iter().enumerate() {
+            flock.validate().map_err(|problem| ScenarioError::Flock(i, problem))?;
+        }
+        Ok(())
+    }
+
+    /// Creates the world and spawns every flock into it, drawing from the
+    /// world's seeded RNG so the same scenario and seed always give the
+    /// same starting state.
+    pub fn build(&self) -> Result<World, ScenarioError> {
+        self.validate()?;
+        let mut world = World::with_seed(self.side_len, self.cells_per_side, self.seed.unwrap_or(DEFAULT_SEED));
+        world.set_boundary(self.boundary);
+        *world.steering_mut() = self.steering;
+        for (group, flock) in self.flocks.iter().enumerate() {
+            flock.spawn_into(&mut world, group as u32);
+        }
+        Ok(world)
+    }
+}
+
+impl Flock {
+    fn validate(&self) -> Result<(), &'static str> {
+        let finite = |v: Vec3| v.is_finite();
+        match &self.spawn {
+            Spawn::UniformBox { min, max } if !(finite(*min) && finite(*max)) => return Err("box corners must be finite"),
+            Spawn::UniformBox { min, max } if min.cmpgt(*max).any() => return Err("box min must not exceed its max"),
+            Spawn::SphereShell { center, inner_radius, outer_radius } => {
+                if !(finite(*center) && inner_radius.is_finite() && outer_radius.is_finite()) {
+                    return Err("sphere shell must be finite");
+                }
+                if *inner_radius < 0.0 || inner_radius > outer_radius {
+                    return Err("sphere shell radii must satisfy 0 <= inner <= outer");
+                }
+            }
+            Spawn::Gaussian { center, std_dev } if !(finite(*center) && std_dev.is_finite() && *std_dev >= 0.0) => {
+                return Err("gaussian spread must be a non-negative number");
+            }
+            Spawn::Explicit(positions) if !positions.iter().all(|&p| finite(p)) => {
+                return Err("explicit positions must be finite");
+            }
+            _ => {}
+        }
+        match self.velocity {
+            Velocity::Constant(v) if !finite(v) => Err("constant velocity must be finite"),
+            Velocity::Random { min_speed, max_speed }
+                if !(min_speed.is_finite() && max_speed.is_finite() && 0.0 <= min_speed && min_speed <= max_speed) =>
+            {
+                Err("random speeds must satisfy 0 <= min <= max")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn spawn_into(&self, world: &mut World, group: u32) {
+        if let Spawn::Explicit(positions) = &self.spawn {
+            for &pos in positions {
+                let vel = self.velocity.sample(world);
+                world.add_boid_to_group(pos, vel, group);
+            }
+            return;
+        }
+
+        for _ in 0..self.count {
+            let pos = self.spawn.sample(world);
+            let vel = self.velocity.sample(world);
+            world.add_boid_to_group(pos, vel, group);
+        }
+    }
+}
+
+impl Spawn {
+    fn sample(&self, world: &mut World) -> Vec3 {
+        match *self {
+            Spawn::UniformBox { min, max } => {
+                let rng = world.rng();
+                Vec3::new(
+                    lerp(min.x, max.x, rng.gen()),
+                    lerp(min.y, max.y, rng.gen()),
+                    lerp(min.z, max.z, rng.gen()),
+                )
+            }
+            Spawn::SphereShell { center, inner_radius, outer_radius } => {
+                let dir = world.random_unit_vector();
+                // uniform by volume, so the outer part of the shell isn't sparser
+                let (inner, outer) = (inner_radius.powi(3), outer_radius.powi(3));
+                let r = lerp(inner, outer, world.rng().gen()).cbrt();
+                center + r * dir
+            }
+            Spawn::Gaussian { center, std_dev } => {
+                let rng = world.rng();
+                center + std_dev * Vec3::new(gaussian(rng), gaussian(rng), gaussian(rng))
+            }
+            Spawn::Explicit(_) => unreachable!("explicit positions are spawned one by one"),
+        }
+    }
+}
+
+impl Velocity {
+    fn sample(&self, world: &mut World) -> Vec3 {
+        match *self {
+            Velocity::Zero => Vec3::ZERO,
+            Velocity::Constant(v) => v,
+            Velocity::Random { min_speed, max_speed } => {
+                let dir = world.random_unit_vector();
+                dir * lerp(min_speed, max_speed, world.rng().gen())
+            }
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// A standard normal sample, by the Box-Muller transform.
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            side_len: 10.0,
+            cells_per_side: 12,
+            boundary: Boundary::default(),
+            seed: None,
+            steering: Steering::default(),
+            flocks: vec![Flock {
+                count: 500,
+                spawn: Spawn::UniformBox { min: Vec3::splat(-4.0), max: Vec3::splat(4.0) },
+                velocity: Velocity::default(),
+            }],
+        }
+    }
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Velocity::Random { min_speed: 0.2, max_speed: 0.6 }
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "malformed scenario: {}", e),
+            ScenarioError::World(e) => write!(f, "invalid scenario: {}", e),
+            ScenarioError::Flock(i, problem) => write!(f, "invalid scenario: flock {}: {}", i, problem),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+impl From<ron::Error> for ScenarioError {
+    fn from(e: ron::Error) -> Self {
+        ScenarioError::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::{Scenario, ScenarioError, Spawn, Velocity};
+    use crate::world::{Boundary, InvalidSetting, MAX_CELLS_PER_SIDE};
+
+    #[test]
+    fn the_bundled_scenarios_parse() {
+        for source in [
+            include_str!("../scenarios/default.ron"),
+            include_str!("../scenarios/two_flocks.ron"),
+        ] {
+            let scenario = Scenario::from_ron(source).unwrap();
+            assert!(!scenario.flocks.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_spawns_every_flock() {
+        let scenario = Scenario::from_ron(r#"(
+            side_len: 20.0,
+            boundary: Wrap,
+            seed: Some(5),
+            flocks: [
+                (count: 30, spawn: SphereShell(center: (1.0, 2.0, 3.0), inner_radius: 2.0, outer_radius: 3.0)),
+                (count: 20, spawn: Gaussian(center: (0.0, 0.0, 0.0), std_dev: 0.5), velocity: Zero),
+                (spawn: Explicit([(1.0, 1.0, 1.0), (-1.0, -1.0, -1.0)]), velocity: Constant((0.0, 1.0, 0.0))),
+            ],
+        )"#).unwrap();
+        let world = scenario.build().unwrap();
+
+        assert_eq!(world.boids().len(), 52);
+        assert_eq!(world.side_len(), 20.0);
+        assert_eq!(world.boundary(), Boundary::Wrap);
+        assert_eq!(world.seed(), 5);
+        for boid in &world.boids()[..30] {
+            let r = boid.position().distance(Vec3::new(1.0, 2.0, 3.0));
+            assert!((2.0..=3.0 + 1e-4).contains(&r));
+        }
+        assert!(world.boids()[30..50].iter().all(|b| b.velocity() == Vec3::ZERO));
+        assert_eq!(world.boids()[51].position(), Vec3::splat(-1.0));
+        assert_eq!(world.boids()[51].velocity(), Vec3::Y);
+    }
+
+    #[test]
+    fn building_twice_gives_the_same_world() {
+        let scenario = Scenario {
+            seed: Some(11),
+            ..Scenario::default()
+        };
+
+        assert_eq!(scenario.build().unwrap().state_hash(), scenario.build().unwrap().state_hash());
+    }
+
+    #[test]
+    fn uniform_boxes_stay_inside_their_bounds() {
+        let mut scenario = Scenario::default();
+        scenario.flocks[0].spawn = Spawn::UniformBox { min: Vec3::new(0.0, 1.0, 2.0), max: Vec3::new(1.0, 2.0, 3.0) };
+        let world = scenario.build().unwrap();
+
+        assert!(world.boids().iter().all(|b| {
+            let p = b.position();
+            p.cmpge(Vec3::new(0.0, 1.0, 2.0)).all() && p.cmple(Vec3::new(1.0, 2.0, 3.0)).all()
+        }));
+    }
+
+    #[test]
+    fn impossible_worlds_are_rejected_rather_than_built() {
+        let invalid = |scenario: Scenario| match scenario.build() {
+            Err(ScenarioError::World(e)) => e,
+            other => panic!("expected an invalid world, got {:?}", other.map(|w| w.boids().len())),
+        };
+        let zero_cells = Scenario { cells_per_side: 0, ..Scenario::default() };
+        assert_eq!(invalid(zero_cells), InvalidSetting::CellsPerSide(0));
+        let huge_grid = Scenario { cells_per_side: MAX_CELLS_PER_SIDE + 1, ..Scenario::default() };
+        assert_eq!(invalid(huge_grid), InvalidSetting::CellsPerSide(MAX_CELLS_PER_SIDE + 1));
+        let flat = Scenario { side_len: 0.0, boundary: Boundary::Wrap, ..Scenario::default() };
+        assert_eq!(invalid(flat), InvalidSetting::SideLength(0.0));
+        let inside_out = Scenario { side_len: -5.0, ..Scenario::default() };
+        assert_eq!(invalid(inside_out), InvalidSetting::SideLength(-5.0));
+        assert!(matches!(invalid(Scenario { side_len: f32::INFINITY, ..Scenario::default() }), InvalidSetting::SideLength(_)));
+
+        let mut blind = Scenario::default();
+        blind.steering.cohesion.radius = -1.0;
+        assert_eq!(invalid(blind), InvalidSetting::Steering("cohesion radius", -1.0));
+        let mut unweighted = Scenario::default();
+        unweighted.steering.alignment.weight = f32::INFINITY;
+        assert_eq!(invalid(unweighted), InvalidSetting::Weight("alignment weight", f32::INFINITY));
+        let outwards = Scenario { boundary: Boundary::SteerBack { margin: -1.0, strength: 2.0 }, ..Scenario::default() };
+        assert_eq!(invalid(outwards), InvalidSetting::Boundary("boundary margin", -1.0));
+        let repelled = Scenario { boundary: Boundary::SteerBack { margin: 1.0, strength: -2.0 }, ..Scenario::default() };
+        assert_eq!(invalid(repelled), InvalidSetting::Boundary("boundary strength", -2.0));
+        let unsteered = Scenario { boundary: Boundary::SteerBack { margin: 1.0, strength: f32::NAN }, ..Scenario::default() };
+        assert!(matches!(invalid(unsteered), InvalidSetting::Boundary("boundary strength", _)));
+    }
+
+    #[test]
+    fn impossible_flocks_are_rejected_rather_than_spawned() {
+        let spawns = [
+            Spawn::SphereShell { center: Vec3::ZERO, inner_radius: -1.0, outer_radius: 2.0 },
+            Spawn::SphereShell { center: Vec3::ZERO, inner_radius: 3.0, outer_radius: 2.0 },
+            Spawn::Gaussian { center: Vec3::ZERO, std_dev: -0.5 },
+            Spawn::UniformBox { min: Vec3::ONE, max: Vec3::ZERO },
+            Spawn::Explicit(vec![Vec3::new(f32::NAN, 0.0, 0.0)]),
+        ];
+        for spawn in spawns {
+            let mut scenario = Scenario::default();
+            scenario.flocks.push(scenario.flocks[0].clone());
+            scenario.flocks[1].spawn = spawn;
+            assert!(matches!(scenario.build(), Err(ScenarioError::Flock(1, _))));
+        }
+
+        let mut slow = Scenario::default();
+        slow.flocks[0].velocity = Velocity::Random { min_speed: -1.0, max_speed: 1.0 };
+        assert!(matches!(slow.validate(), Err(ScenarioError::Flock(0, _))));
+
+        // counts are unsigned, so a negative one doesn't even parse
+        let negative = Scenario::from_ron("(flocks: [(count: -5, spawn: Gaussian(center: (0.0, 0.0, 0.0), std_dev: 1.0))])");
+        assert!(matches!(negative, Err(ScenarioError::Parse(_))));
+    }
+}
@@ -1,10 +1,23 @@
 struct FragInput {
     [[builtin(position)]] pos: vec4<f32>;
-    [[location(0)]] color: vec3<f32>;
+    [[location(0)]] color: vec4<f32>;
+    [[location(1)]] normal: vec3<f32>;
 };
 
+struct Lighting {
+    // the way the light shines, normalized
+    direction: vec4<f32>;
+    color: vec4<f32>;
+    ambient: vec4<f32>;
+};
+
+[[group(0), binding(1)]]
+var<uniform> lighting: Lighting;
 
 [[stage(fragment)]]
 fn main(in: FragInput) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+    let normal = normalize(in.normal);
+    let diffuse = max(dot(normal, -lighting.direction.xyz), 0.0);
+    let light = lighting.ambient.rgb + diffuse * lighting.color.rgb;
+    return vec4<f32>(in.color.rgb * light, in.color.a);
+}
@@ -1,44 +1,46 @@
 struct VertexInput {
-    [[location(0)]] position: vec4<f32>;
-    [[location(1)]]  color: vec4<f32>;
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] color: vec4<f32>;
+    [[location(3)]] uv: vec2<f32>;
 };
 
 struct InstanceInput {
-    [[location(2)]] mvp_0: vec4<f32>;
-    [[location(3)]] mvp_1: vec4<f32>;
-    [[location(4)]] mvp_2: vec4<f32>;
-    [[location(5)]] mvp_3: vec4<f32>;
+    // xyz is the boid's position, w its uniform scale
+    [[location(4)]] position_scale: vec4<f32>;
+    [[location(5)]] rotation: vec4<f32>;
+    [[location(6)]] tint: vec4<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] pos: vec4<f32>;
-    [[location(0)]] color: vec3<f32>;
+    [[location(0)]] color: vec4<f32>;
+    [[location(1)]] normal: vec3<f32>;
 };
 
 struct MatrixData {
-    mvp: mat4x4<f32>;
+    view_proj: mat4x4<f32>;
 };
 
 [[group(0), binding(0)]]
 var<uniform> uni_data: MatrixData;
 
+// rotates v by the unit quaternion q
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
 [[stage(vertex)]]
 fn main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
-    let mvp = mat4x4<f32>(
-        instance.mvp_0,
-        instance.mvp_1,
-        instance.mvp_2,
-        instance.mvp_3,
-    );
+    let local = rotate(instance.rotation, vertex.position * instance.position_scale.w);
+    let world_pos = local + instance.position_scale.xyz;
+
     var out: VertexOutput;
-    let identity = mat4x4<f32>(
-        1.0, 0.0, 0.0, 0.0,
-        0.0, 1.0, 0.0, 0.0,
-        0.0, 0.0, 1.0, 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-    out.pos = mvp * vec4<f32>(vertex.position.xyz, 1.0);
-    // out.pos = vec4<f32>(vertex.position, 1.0);
-    out.color = vertex.color.xzy;
+    out.pos = uni_data.view_proj * vec4<f32>(world_pos, 1.0);
+    // the tint replaces the mesh's color as far as its alpha says
+    out.color = vec4<f32>(mix(vertex.color.rgb, instance.tint.rgb, instance.tint.a), vertex.color.a);
+    // the scale is uniform, so rotating the normal is enough
+    out.normal = rotate(instance.rotation, vertex.normal);
     return out;
-}
\ No newline at end of file
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+/// Simulation ticks the world runs at unless configured otherwise.
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// Frame times longer than this are cut short, so a stall (e.g. dragging the
+/// window) doesn't leave the simulation trying to catch up for seconds.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Accumulates wall-clock frame time and hands it out in fixed-size ticks.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn from_hz(hz: f64) -> Self {
+        assert!(hz > 0.0, "tick rate must be positive");
+        Self {
+            step: Duration::from_secs_f64(1.0 / hz),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds `frame_time` to the accumulator and returns how many whole ticks
+    /// are now due, removing them from the accumulator.
+    pub fn advance(&mut self, frame_time: Duration) -> u32 {
+        self.accumulator += frame_time.min(MAX_FRAME_TIME);
+        let mut ticks = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far between the previous and the current tick the display is, in
+    /// `[0, 1)`; used to interpolate rendered state.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::from_hz(DEFAULT_TICK_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FixedTimestep;
+
+    #[test]
+    fn it_hands_out_whole_ticks_and_keeps_the_remainder() {
+        let mut timestep = FixedTimestep::from_hz(100.0);
+
+        assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+        assert!(timestep.alpha() < 1e-4);
+        assert_eq!(timestep.advance(Duration::from_millis(3)), 0);
+    }
+
+    #[test]
+    fn long_frames_are_capped() {
+        let mut timestep = FixedTimestep::from_hz(100.0);
+
+        assert_eq!(timestep.advance(Duration::from_secs(10)), 25);
+    }
+}
@@ -1,6 +1,25 @@
-use glam::{Vec3, Mat4};
-use super::BoidInstance;
+use std::{fmt, time::Duration};
 
+use glam::{Vec3, UVec3, IVec3};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+pub use snapshot::{Format, SnapshotError};
+
+mod snapshot;
+
+/// Half the side length of the box each boid occupies.
+const BOID_HALF_EXTENT: f32 = 0.05;
+
+/// Seed used by `World::new`.
+pub const DEFAULT_SEED: u64 = 0x1d1d_1d1d;
+
+/// The most grid cells along each side of a world. Any more and the spatial
+/// hash alone would run to gigabytes.
+pub const MAX_CELLS_PER_SIDE: usize = 128;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug)]
 struct AABB {
     min: Vec3,
@@ -13,29 +32,119 @@ struct AABBIter {
 }
 
 #[derive(Clone, Copy, Debug)]
-struct Boid {
+pub struct Boid {
     position: Vec3,
+    velocity: Vec3,
+    acceleration: Vec3,
     aabb: AABB,
+    /// Which flock the boid was spawned in. Doesn't affect steering.
+    group: u32,
+}
+
+/// Weight and perception radius of a single steering rule.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub weight: f32,
+    pub radius: f32,
 }
 
+/// Tunables for separation, alignment and cohesion.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Steering {
+    pub separation: Rule,
+    pub alignment: Rule,
+    pub cohesion: Rule,
+    pub max_speed: f32,
+    pub max_force: f32,
+}
+
+/// Maps world-space positions onto the uniform grid backing the spatial hash.
+/// Cells are laid out x-major: index = x + y * n + z * n * n.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Grid {
+    min_corner: Vec3,
+    cell_size: f32,
+    cells_per_side: usize,
+}
+
+/// Every grid cell in the inclusive box of cell coordinates `lo..=hi`, each
+/// visited exactly once. Coordinates outside the grid are wrapped around to
+/// the opposite side when `wrap` is set; otherwise `lo` and `hi` are already
+/// clamped.
 struct BoidCellsIter {
-    cells: [u64; 8],
+    lo: IVec3,
+    hi: IVec3,
+    next: Option<IVec3>,
+    wrap: bool,
+    grid: Grid,
+}
+
+/// Summary of the flock's state at one point in time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlockStats {
+    pub boids: usize,
+    pub centroid: Vec3,
+    pub mean_speed: f32,
+    pub max_speed: f32,
+    /// Length of the mean heading: 1 when every boid flies the same way,
+    /// close to 0 for a disordered flock.
+    pub polarization: f32,
+}
+
+/// What happens to boids that reach the edge of the world.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Boundary {
+    /// Toroidal wraparound: leaving through one face re-enters through the
+    /// opposite one, and neighbors are found across the seam.
+    Wrap,
+    /// Elastic reflection off the walls.
+    Bounce,
+    /// Boids within `margin` of a wall are steered back inwards with a force
+    /// growing linearly up to `strength` at the wall itself.
+    SteerBack { margin: f32, strength: f32 },
+}
+
+/// A world setting that no world can be built with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidSetting {
+    /// The side length isn't a positive number.
+    SideLength(f32),
+    /// Not between 1 and `MAX_CELLS_PER_SIDE` cells along each side.
+    CellsPerSide(usize),
+    /// A steering radius or limit, by name, that's negative or not a number.
+    Steering(&'static str, f32),
+    /// A steering rule's weight, by name, that isn't a finite number.
+    Weight(&'static str, f32),
+    /// A `SteerBack` margin or strength, by name, that's negative or not a
+    /// number, and so would push boids outwards.
+    Boundary(&'static str, f32),
 }
 
 struct Cell {
     min: Vec3,
     max: Vec3,
-    boids_inside: Vec<Boid>,
+    /// Indices into `World::boids` of every boid whose AABB overlaps this cell.
+    boids_inside: Vec<usize>,
 }
 
-// For now, the world is a cube
+// For now, the world is a cube centered on the origin
+//
+// Stepping is deterministic: boids are always visited in insertion order and
+// neighbor lists come back sorted, so floating point sums happen in the same
+// order every run, and all randomness comes from the seeded `rng`.
 pub struct World {
     length: f32,
     width: f32,
     height: f32,
-    cell_size: f32,
+    grid: Grid,
     boids: Vec<Boid>,
     hash_table: Vec<Cell>,
+    steering: Steering,
+    boundary: Boundary,
+    seed: u64,
+    rng: Pcg32,
+    tick: u64,
 }
 
 impl AABB {
@@ -43,6 +152,10 @@ impl AABB {
         Self { min, max }
     }
 
+    fn around(center: Vec3, half_extent: f32) -> Self {
+        Self::new(center - half_extent, center + half_extent)
+    }
+
     fn points(&self) -> AABBIter {
         let points = [
             self.min,
@@ -74,36 +187,247 @@ impl Iterator for AABBIter {
 }
 
 impl Boid {
-    fn new(position: Vec3) -> Self {
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn acceleration(&self) -> Vec3 {
+        self.acceleration
+    }
+
+    pub fn group(&self) -> u32 {
+        self.group
+    }
+
+    fn new(position: Vec3, velocity: Vec3, group: u32) -> Self {
         Self {
             position,
-            aabb: AABB::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)),
+            velocity,
+            acceleration: Vec3::ZERO,
+            aabb: AABB::around(position, BOID_HALF_EXTENT),
+            group,
+        }
+    }
+
+    /// Sums the weighted separation, alignment and cohesion forces acting on
+    /// this boid from `others`, each paired with its offset from this boid.
+    fn steering_force<'a>(&self, others: impl Iterator<Item = (Vec3, &'a Boid)>, steering: &Steering) -> Vec3 {
+        let mut separation = Vec3::ZERO;
+        let mut alignment = Vec3::ZERO;
+        let mut cohesion = Vec3::ZERO;
+        let (mut n_align, mut n_cohere) = (0, 0);
+
+        for (offset, other) in others {
+            let dist = offset.length();
+            if dist == 0.0 {
+                continue;
+            }
+            if dist < steering.separation.radius {
+                // push away harder the closer the neighbor is
+                separation -= offset / (dist * dist);
+            }
+            if dist < steering.alignment.radius {
+                alignment += other.velocity;
+                n_align += 1;
+            }
+            if dist < steering.cohesion.radius {
+                cohesion += offset;
+                n_cohere += 1;
+            }
+        }
+
+        let mut acceleration = Vec3::ZERO;
+        if separation != Vec3::ZERO {
+            acceleration += steering.separation.weight * self.seek(separation, steering);
+        }
+        if n_align > 0 {
+            acceleration += steering.alignment.weight * self.seek(alignment / n_align as f32, steering);
+        }
+        if n_cohere > 0 {
+            let to_center = cohesion / n_cohere as f32;
+            acceleration += steering.cohesion.weight * self.seek(to_center, steering);
+        }
+        acceleration
+    }
+
+    /// Reynolds steering: the force that turns the current velocity towards
+    /// `direction` at full speed, limited to `max_force`.
+    fn seek(&self, direction: Vec3, steering: &Steering) -> Vec3 {
+        let desired = direction.normalize_or_zero() * steering.max_speed;
+        (desired - self.velocity).clamp_length_max(steering.max_force)
+    }
+
+    fn integrate(&mut self, dt: f32, max_speed: f32) {
+        self.velocity = (self.velocity + self.acceleration * dt).clamp_length_max(max_speed);
+        self.position += self.velocity * dt;
+        self.aabb = AABB::around(self.position, BOID_HALF_EXTENT);
+    }
+
+    /// The cells this boid's AABB overlaps. Parts of the AABB lying outside
+    /// the world wrap around to the opposite side if `wrap` is set and are
+    /// clamped onto the border cells otherwise, so boids that stray out of
+    /// bounds are still found by neighbor queries.
+    fn grid_cells(&self, grid: &Grid, wrap: bool) -> BoidCellsIter {
+        grid.cells_overlapping(self.aabb, wrap)
+    }
+
+    /// Pushes the boid back inside `[min, max]` along each axis it has left.
+    fn bounce(&mut self, min: Vec3, max: Vec3) {
+        for axis in 0..3 {
+            if self.position[axis] < min[axis] {
+                self.position[axis] = 2.0 * min[axis] - self.position[axis];
+                self.velocity[axis] = self.velocity[axis].abs();
+            } else if self.position[axis] > max[axis] {
+                self.position[axis] = 2.0 * max[axis] - self.position[axis];
+                self.velocity[axis] = -self.velocity[axis].abs();
+            }
+        }
+        // a boid overshooting by more than the world's width would still be outside
+        self.position = self.position.clamp(min, max);
+        self.aabb = AABB::around(self.position, BOID_HALF_EXTENT);
+    }
+
+    fn wrap(&mut self, min: Vec3, side_len: f32) {
+        let rel = self.position - min;
+        self.position = min + Vec3::new(
+            rel.x.rem_euclid(side_len),
+            rel.y.rem_euclid(side_len),
+            rel.z.rem_euclid(side_len),
+        );
+        self.aabb = AABB::around(self.position, BOID_HALF_EXTENT);
+    }
+}
+
+impl Grid {
+    fn new(side_len: f32, cells_per_side: usize) -> Self {
+        Self {
+            min_corner: Vec3::splat(-0.5 * side_len),
+            cell_size: side_len / cells_per_side as f32,
+            cells_per_side,
         }
     }
 
-    fn grid_cells(&self, length: f32, width: f32, conv_factor: f32) -> BoidCellsIter {
-        let conv = |p: Vec3| (conv_factor * (p.x + p.y * width + p.z * length * width)) as u64;
-        let mut points = self.aabb.points();
-        let first = conv(points.next().unwrap());
-        let second = conv(points.next().unwrap());
+    fn num_cells(&self) -> usize {
+        self.cells_per_side * self.cells_per_side * self.cells_per_side
+    }
 
-        if first == second {
-            return BoidCellsIter {
-                cells: [first, first, first, first, first, first, first, first],
-            };
+    fn side_len(&self) -> f32 {
+        self.cell_size * self.cells_per_side as f32
+    }
+
+    /// Coordinates of the cell containing `p`, or `None` if `p` lies outside
+    /// the world. Points on a boundary between two cells belong to the upper
+    /// one, except on the world's upper faces which belong to the last cell.
+    fn cell_coords(&self, p: Vec3) -> Option<UVec3> {
+        let rel = p - self.min_corner;
+        let side = self.side_len();
+        if rel.cmplt(Vec3::ZERO).any() || rel.cmpgt(Vec3::splat(side)).any() || rel.is_nan() {
+            return None;
         }
+        Some(self.clamped_coords(p))
+    }
+
+    /// Like `cell_coords`, but points outside the world snap to the nearest
+    /// border cell.
+    fn clamped_coords(&self, p: Vec3) -> UVec3 {
+        let last = (self.cells_per_side - 1) as f32;
+        ((p - self.min_corner) / self.cell_size).floor()
+            .clamp(Vec3::ZERO, Vec3::splat(last))
+            .as_uvec3()
+    }
 
+    fn index(&self, coords: UVec3) -> usize {
+        let n = self.cells_per_side;
+        coords.x as usize + coords.y as usize * n + coords.z as usize * n * n
+    }
+
+    fn cells_overlapping(&self, aabb: AABB, wrap: bool) -> BoidCellsIter {
+        let (lo, hi) = if wrap {
+            let lo = ((aabb.min - self.min_corner) / self.cell_size).floor().as_ivec3();
+            let hi = ((aabb.max - self.min_corner) / self.cell_size).floor().as_ivec3();
+            // past one full lap every cell along that axis is already covered
+            (lo, hi.min(lo + IVec3::splat(self.cells_per_side as i32 - 1)))
+        } else {
+            (self.clamped_coords(aabb.min).as_ivec3(), self.clamped_coords(aabb.max).as_ivec3())
+        };
         BoidCellsIter {
-            cells: [
-                first,
-                second,
-                conv(points.next().unwrap()),
-                conv(points.next().unwrap()),
-                conv(points.next().unwrap()),
-                conv(points.next().unwrap()),
-                conv(points.next().unwrap()),
-                conv(points.next().unwrap()),
-            ],
+            lo,
+            hi,
+            next: Some(lo),
+            wrap,
+            grid: *self,
+        }
+    }
+}
+
+impl Iterator for BoidCellsIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cur = self.next?;
+        let mut next = cur;
+        next.x += 1;
+        if next.x > self.hi.x {
+            next.x = self.lo.x;
+            next.y += 1;
+            if next.y > self.hi.y {
+                next.y = self.lo.y;
+                next.z += 1;
+            }
+        }
+        self.next = if next.z > self.hi.z { None } else { Some(next) };
+
+        let cur = if self.wrap {
+            let n = self.grid.cells_per_side as i32;
+            IVec3::new(cur.x.rem_euclid(n), cur.y.rem_euclid(n), cur.z.rem_euclid(n))
+        } else {
+            cur
+        };
+        Some(self.grid.index(cur.as_uvec3()))
+    }
+}
+
+impl Steering {
+    /// The largest radius any rule looks at, i.e. how far a boid needs to
+    /// search for neighbors.
+    pub fn perception_radius(&self) -> f32 {
+        self.separation.radius.max(self.alignment.radius).max(self.cohesion.radius)
+    }
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Self {
+            separation: Rule { weight: 1.5, radius: 0.25 },
+            alignment: Rule { weight: 1.0, radius: 0.5 },
+            cohesion: Rule { weight: 1.0, radius: 0.5 },
+            max_speed: 1.0,
+            max_force: 0.5,
+        }
+    }
+}
+
+impl Default for Boundary {
+    fn default() -> Self {
+        Boundary::SteerBack { margin: 1.0, strength: 2.0 }
+    }
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidSetting::SideLength(v) => write!(f, "side length must be a positive number, not {}", v),
+            InvalidSetting::CellsPerSide(n) => {
+                write!(f, "cells per side must be between 1 and {}, not {}", MAX_CELLS_PER_SIDE, n)
+            }
+            InvalidSetting::Steering(name, v) | InvalidSetting::Boundary(name, v) => {
+                write!(f, "{} must be a non-negative number, not {}", name, v)
+            }
+            InvalidSetting::Weight(name, v) => write!(f, "{} must be a finite number, not {}", name, v),
         }
     }
 }
@@ -119,52 +443,338 @@ impl Cell {
 }
 
 impl World {
+    /// Checks settings read from a file before a world is built with them,
+    /// since `with_seed` trusts its arguments.
+    pub fn check_settings(
+        side_len: f32,
+        cells_per_side: usize,
+        steering: &Steering,
+        boundary: &Boundary,
+    ) -> Result<(), InvalidSetting> {
+        if !(side_len.is_finite() && side_len > 0.0) {
+            return Err(InvalidSetting::SideLength(side_len));
+        }
+        if !(1..=MAX_CELLS_PER_SIDE).contains(&cells_per_side) {
+            return Err(InvalidSetting::CellsPerSide(cells_per_side));
+        }
+        let limits = [
+            ("separation radius", steering.separation.radius),
+            ("alignment radius", steering.alignment.radius),
+            ("cohesion radius", steering.cohesion.radius),
+            ("max speed", steering.max_speed),
+            ("max force", steering.max_force),
+        ];
+        if let Some((name, v)) = limits.into_iter().find(|&(_, v)| !(v.is_finite() && v >= 0.0)) {
+            return Err(InvalidSetting::Steering(name, v));
+        }
+        let weights = [
+            ("separation weight", steering.separation.weight),
+            ("alignment weight", steering.alignment.weight),
+            ("cohesion weight", steering.cohesion.weight),
+        ];
+        if let Some((name, v)) = weights.into_iter().find(|&(_, v)| !v.is_finite()) {
+            return Err(InvalidSetting::Weight(name, v));
+        }
+        if let Boundary::SteerBack { margin, strength } = *boundary {
+            let steer_back = [("boundary margin", margin), ("boundary strength", strength)];
+            if let Some((name, v)) = steer_back.into_iter().find(|&(_, v)| !(v.is_finite() && v >= 0.0)) {
+                return Err(InvalidSetting::Boundary(name, v));
+            }
+        }
+        Ok(())
+    }
+
     pub fn new(side_len: f32, cells_per_side: usize) -> Self {
-        let num_cells = cells_per_side * cells_per_side * cells_per_side;
+        Self::with_seed(side_len, cells_per_side, DEFAULT_SEED)
+    }
 
-        // TODO: actually use the hash table
-        let hash_table = Vec::with_capacity(num_cells);
-        // hash_table.resize(num_cells, Cell::new());
+    pub fn with_seed(side_len: f32, cells_per_side: usize, seed: u64) -> Self {
+        assert!(cells_per_side > 0, "the world needs at least one cell per side");
+        let grid = Grid::new(side_len, cells_per_side);
+
+        let mut hash_table = Vec::with_capacity(grid.num_cells());
+        for z in 0..cells_per_side {
+            for y in 0..cells_per_side {
+                for x in 0..cells_per_side {
+                    let min = grid.min_corner + Vec3::new(x as f32, y as f32, z as f32) * grid.cell_size;
+                    hash_table.push(Cell::new(min, min + grid.cell_size));
+                }
+            }
+        }
 
         Self {
             length: side_len,
             width: side_len,
             height: side_len,
-            cell_size: 0.0,
+            grid,
             boids: Vec::new(),
             hash_table,
+            steering: Steering::default(),
+            boundary: Boundary::default(),
+            seed,
+            rng: Pcg32::seed_from_u64(seed),
+            tick: 0,
         }
     }
 
     pub fn add_boid(&mut self, pos: Vec3) {
-        self.boids.push(Boid::new(pos));
+        self.add_boid_with_velocity(pos, Vec3::ZERO);
+    }
+
+    pub fn add_boid_with_velocity(&mut self, pos: Vec3, vel: Vec3) {
+        self.add_boid_to_group(pos, vel, 0);
+    }
+
+    /// Adds a boid tagged as belonging to flock `group`, e.g. for coloring.
+    pub fn add_boid_to_group(&mut self, pos: Vec3, vel: Vec3, group: u32) {
+        let boid = Boid::new(pos, vel, group);
+        let i = self.boids.len();
+        for cell in boid.grid_cells(&self.grid, self.wraps()) {
+            self.hash_table[cell].boids_inside.push(i);
+        }
+        self.boids.push(boid);
     }
 
-    pub fn update(&mut self) {
-        // for boid in self.boids.iter_mut() {
-        //     boid.position += 0.01;
-        // }
+    /// Adds `count` boids at uniformly random positions inside the world,
+    /// heading in random directions at up to `max_speed`.
+    pub fn spawn_random(&mut self, count: usize, max_speed: f32) {
+        let half = 0.5 * self.side_len();
+        for _ in 0..count {
+            let pos = Vec3::new(
+                self.rng.gen_range(-half..half),
+                self.rng.gen_range(-half..half),
+                self.rng.gen_range(-half..half),
+            );
+            let vel = self.random_unit_vector() * self.rng.gen_range(0.0..=max_speed);
+            self.add_boid_with_velocity(pos, vel);
+        }
     }
 
-    pub(crate) fn fill_instance_buffer(&self, buff: &mut Vec<BoidInstance>, view: Mat4, proj: Mat4) {
-        buff.clear();
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts the random number generator from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Pcg32::seed_from_u64(seed);
+    }
+
+    /// The world's random number generator. Anything random that should
+    /// be reproducible from the seed has to draw from this.
+    pub fn rng(&mut self) -> &mut Pcg32 {
+        &mut self.rng
+    }
+
+    /// Number of ticks run since the world was created.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// A hash of the simulation state: the tick count and every boid's
+    /// position, velocity and acceleration, bit for bit. Two runs from the
+    /// same seed and inputs produce equal hashes.
+    pub fn state_hash(&self) -> u64 {
+        // FNV-1a, so the value doesn't depend on the std hasher's implementation
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |word: u64| {
+            for byte in word.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(PRIME);
+            }
+        };
+
+        feed(self.tick);
+        feed(self.boids.len() as u64);
         for boid in self.boids.iter() {
-            let trans = Mat4::from_translation(boid.position);
-            let inst = BoidInstance {
-                mvp: proj * view * trans,
-                // mvp: Mat4::IDENTITY,
-            };
-            buff.push(inst);
+            for v in [boid.position, boid.velocity, boid.acceleration] {
+                v.to_array().iter().for_each(|c| feed(c.to_bits() as u64));
+            }
+        }
+        hash
+    }
+
+    pub(crate) fn random_unit_vector(&mut self) -> Vec3 {
+        // uniform on the sphere: uniform height and angle around the axis
+        let z: f32 = self.rng.gen_range(-1.0..=1.0);
+        let theta: f32 = self.rng.gen_range(0.0..std::f32::consts::TAU);
+        let r = (1.0 - z * z).sqrt();
+        Vec3::new(r * theta.cos(), r * theta.sin(), z)
+    }
+
+    pub fn boundary(&self) -> Boundary {
+        self.boundary
+    }
+
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.boundary = boundary;
+        // switching into or out of wrapping changes which cells border boids land in
+        self.rebuild_hash();
+    }
+
+    pub fn boids(&self) -> &[Boid] {
+        &self.boids
+    }
+
+    pub fn side_len(&self) -> f32 {
+        self.grid.side_len()
+    }
+
+    pub fn cells_per_side(&self) -> usize {
+        self.grid.cells_per_side
+    }
+
+    pub fn steering(&self) -> &Steering {
+        &self.steering
+    }
+
+    pub fn steering_mut(&mut self) -> &mut Steering {
+        &mut self.steering
+    }
+
+    pub fn update(&mut self, delta_t: Duration) {
+        let dt = delta_t.as_secs_f32();
+        let radius = self.steering.perception_radius();
+
+        // every boid steers against the flock as it was at the start of the tick
+        let mut neighbors = Vec::new();
+        for i in 0..self.boids.len() {
+            self.neighbors_into(self.boids[i].position, radius, &mut neighbors);
+            let boid = &self.boids[i];
+            let others = neighbors.iter()
+                .filter(|&&j| j != i)
+                .map(|&j| (self.offset(boid.position, self.boids[j].position), &self.boids[j]));
+            let acceleration = boid.steering_force(others, &self.steering) + self.boundary_force(boid.position);
+            self.boids[i].acceleration = acceleration;
+        }
+
+        let min = self.grid.min_corner;
+        let side_len = self.grid.side_len();
+        for boid in self.boids.iter_mut() {
+            boid.integrate(dt, self.steering.max_speed);
+            match self.boundary {
+                Boundary::Wrap => boid.wrap(min, side_len),
+                Boundary::Bounce => boid.bounce(min, min + side_len),
+                Boundary::SteerBack { .. } => (),
+            }
         }
+        self.rebuild_hash();
+        self.tick += 1;
+    }
+
+    pub fn stats(&self) -> FlockStats {
+        let n = self.boids.len().max(1) as f32;
+        let mut centroid = Vec3::ZERO;
+        let mut heading = Vec3::ZERO;
+        let mut total_speed = 0.0;
+        let mut max_speed: f32 = 0.0;
+        for boid in self.boids.iter() {
+            let speed = boid.velocity.length();
+            centroid += boid.position;
+            heading += boid.velocity.normalize_or_zero();
+            total_speed += speed;
+            max_speed = max_speed.max(speed);
+        }
+
+        FlockStats {
+            boids: self.boids.len(),
+            centroid: centroid / n,
+            mean_speed: total_speed / n,
+            max_speed,
+            polarization: heading.length() / n,
+        }
+    }
+
+    /// Indices of every boid whose position is within `radius` of `pos`,
+    /// in ascending order. Only the grid cells overlapping the query sphere's
+    /// bounding box are visited.
+    pub fn neighbors_within(&self, pos: Vec3, radius: f32) -> Vec<usize> {
+        let mut neighbors = Vec::new();
+        self.neighbors_into(pos, radius, &mut neighbors);
+        neighbors
+    }
+
+    /// How many other boids are within `radius` of each boid.
+    pub fn neighbor_counts(&self, radius: f32) -> Vec<usize> {
+        let mut neighbors = Vec::new();
+        self.boids.iter().map(|boid| {
+            self.neighbors_into(boid.position, radius, &mut neighbors);
+            neighbors.len().saturating_sub(1)
+        }).collect()
+    }
+
+    fn neighbors_into(&self, pos: Vec3, radius: f32, out: &mut Vec<usize>) {
+        out.clear();
+        for cell in self.grid.cells_overlapping(AABB::around(pos, radius), self.wraps()) {
+            out.extend(
+                self.hash_table[cell].boids_inside.iter()
+                    .filter(|&&i| self.offset(pos, self.boids[i].position).length_squared() <= radius * radius)
+            );
+        }
+        // boids straddling several cells show up once per cell
+        out.sort_unstable();
+        out.dedup();
+    }
+
+    fn rebuild_hash(&mut self) {
+        for cell in self.hash_table.iter_mut() {
+            cell.boids_inside.clear();
+        }
+        for i in 0..self.boids.len() {
+            for cell in self.boids[i].grid_cells(&self.grid, self.wraps()) {
+                self.hash_table[cell].boids_inside.push(i);
+            }
+        }
+    }
+
+    fn wraps(&self) -> bool {
+        self.boundary == Boundary::Wrap
+    }
+
+    /// The vector from `from` to `to`. When the world wraps this is the
+    /// shortest such vector, which may cross the seam.
+    fn offset(&self, from: Vec3, to: Vec3) -> Vec3 {
+        let d = to - from;
+        if self.wraps() {
+            let side_len = self.grid.side_len();
+            d - side_len * (d / side_len).round()
+        } else {
+            d
+        }
+    }
+
+    /// Inward force on a boid at `pos` from the walls it is close to, if the
+    /// boundary mode steers boids back.
+    fn boundary_force(&self, pos: Vec3) -> Vec3 {
+        let (margin, strength) = match self.boundary {
+            Boundary::SteerBack { margin, strength } => (margin, strength),
+            Boundary::Wrap | Boundary::Bounce => return Vec3::ZERO,
+        };
+        let min = self.grid.min_corner;
+        let max = min + self.grid.side_len();
+        let mut force = Vec3::ZERO;
+        for axis in 0..3 {
+            let to_min = pos[axis] - min[axis];
+            let to_max = max[axis] - pos[axis];
+            if to_min < margin {
+                force[axis] += strength * (1.0 - to_min / margin).min(1.0);
+            } else if to_max < margin {
+                force[axis] -= strength * (1.0 - to_max / margin).min(1.0);
+            }
+        }
+        force
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use glam::Vec3;
+    use std::time::Duration;
 
-    use super::AABB;
+    use glam::{UVec3, Vec3};
+
+    use super::{AABB, Boid, Boundary, Grid, World};
 
     #[test]
     fn the_aabb_iter_works() {
@@ -185,6 +795,289 @@ mod tests {
         assert_eq!(iter.next(), None);
     }
 
+    /// A 4x4x4 grid of unit cells spanning [-2, 2] on every axis.
+    fn unit_grid() -> Grid {
+        Grid::new(4.0, 4)
+    }
+
+    fn cells_of(grid: &Grid, min: Vec3, max: Vec3) -> Vec<usize> {
+        let mut boid = Boid::new(0.5 * (min + max), Vec3::ZERO, 0);
+        boid.aabb = AABB::new(min, max);
+        boid.grid_cells(grid, false).collect()
+    }
+
+    #[test]
+    fn it_computes_sane_grid_cells() {
+        let grid = unit_grid();
+
+        assert_eq!(grid.cell_coords(Vec3::new(-1.5, -1.5, -1.5)), Some(UVec3::new(0, 0, 0)));
+        assert_eq!(grid.cell_coords(Vec3::new(0.5, -0.5, 1.5)), Some(UVec3::new(2, 1, 3)));
+        assert_eq!(grid.index(UVec3::new(2, 1, 3)), 2 + 4 + 3 * 16);
+        assert_eq!(grid.index(UVec3::new(3, 3, 3)), grid.num_cells() - 1);
+    }
+
+    #[test]
+    fn world_corners_map_to_corner_cells() {
+        let grid = unit_grid();
+
+        assert_eq!(grid.cell_coords(Vec3::splat(-2.0)), Some(UVec3::ZERO));
+        assert_eq!(grid.cell_coords(Vec3::splat(2.0)), Some(UVec3::splat(3)));
+        assert_eq!(grid.cell_coords(Vec3::new(2.0, -2.0, 2.0)), Some(UVec3::new(3, 0, 3)));
+    }
+
+    #[test]
+    fn points_on_cell_boundaries_belong_to_the_upper_cell() {
+        let grid = unit_grid();
+
+        assert_eq!(grid.cell_coords(Vec3::new(-1.0, -2.0, -2.0)), Some(UVec3::new(1, 0, 0)));
+        assert_eq!(grid.cell_coords(Vec3::new(0.0, 0.0, 0.0)), Some(UVec3::new(2, 2, 2)));
+        assert_eq!(grid.cell_coords(Vec3::new(1.0, 1.0, -1.0)), Some(UVec3::new(3, 3, 1)));
+    }
+
+    #[test]
+    fn points_outside_the_world_are_rejected() {
+        let grid = unit_grid();
+
+        assert_eq!(grid.cell_coords(Vec3::new(-2.01, 0.0, 0.0)), None);
+        assert_eq!(grid.cell_coords(Vec3::new(0.0, 2.01, 0.0)), None);
+        assert_eq!(grid.cell_coords(Vec3::new(0.0, 0.0, -100.0)), None);
+        assert_eq!(grid.cell_coords(Vec3::new(f32::NAN, 0.0, 0.0)), None);
+        assert_eq!(grid.clamped_coords(Vec3::new(-100.0, 0.5, 100.0)), UVec3::new(0, 2, 3));
+    }
+
+    #[test]
+    fn aabb_inside_one_cell_covers_only_that_cell() {
+        let grid = unit_grid();
+
+        let cells = cells_of(&grid, Vec3::splat(0.2), Vec3::splat(0.8));
+        assert_eq!(cells, vec![grid.index(UVec3::splat(2))]);
+    }
+
     #[test]
-    fn it_computes_sane_grid_cells() {}
+    fn aabb_straddling_cells_lists_each_cell_once() {
+        let grid = unit_grid();
+
+        // straddles one boundary on x only
+        let cells = cells_of(&grid, Vec3::new(-0.1, 0.2, 0.2), Vec3::new(0.1, 0.8, 0.8));
+        assert_eq!(cells, vec![grid.index(UVec3::new(1, 2, 2)), grid.index(UVec3::new(2, 2, 2))]);
+
+        // straddles the shared corner of eight cells
+        let mut cells = cells_of(&grid, Vec3::splat(-0.1), Vec3::splat(0.1));
+        assert_eq!(cells.len(), 8);
+        cells.sort_unstable();
+        cells.dedup();
+        assert_eq!(cells.len(), 8);
+    }
+
+    #[test]
+    fn aabb_touching_a_boundary_does_not_leak_into_the_lower_cell() {
+        let grid = unit_grid();
+
+        let cells = cells_of(&grid, Vec3::new(0.0, 0.2, 0.2), Vec3::new(0.5, 0.8, 0.8));
+        assert_eq!(cells, vec![grid.index(UVec3::new(2, 2, 2))]);
+    }
+
+    #[test]
+    fn aabb_poking_out_of_the_world_is_clamped_to_border_cells() {
+        let grid = unit_grid();
+
+        let cells = cells_of(&grid, Vec3::new(1.9, 1.9, 1.9), Vec3::new(2.1, 2.1, 2.1));
+        assert_eq!(cells, vec![grid.num_cells() - 1]);
+
+        let cells = cells_of(&grid, Vec3::new(-5.0, -0.5, -0.5), Vec3::new(-4.0, -0.4, -0.4));
+        assert_eq!(cells, vec![grid.index(UVec3::new(0, 1, 1))]);
+    }
+
+    fn tick(world: &mut World) {
+        world.update(Duration::from_millis(16));
+    }
+
+    #[test]
+    fn separation_pushes_close_boids_apart() {
+        let mut world = World::new(10.0, 12);
+        world.steering_mut().alignment.weight = 0.0;
+        world.steering_mut().cohesion.weight = 0.0;
+        world.add_boid(Vec3::new(0.0, 0.0, 0.0));
+        world.add_boid(Vec3::new(0.1, 0.0, 0.0));
+
+        tick(&mut world);
+
+        assert!(world.boids[0].position.x < 0.0);
+        assert!(world.boids[1].position.x > 0.1);
+    }
+
+    #[test]
+    fn cohesion_pulls_distant_boids_together() {
+        let mut world = World::new(10.0, 12);
+        world.steering_mut().separation.weight = 0.0;
+        world.steering_mut().alignment.weight = 0.0;
+        world.add_boid(Vec3::new(0.0, 0.0, 0.0));
+        world.add_boid(Vec3::new(0.4, 0.0, 0.0));
+
+        tick(&mut world);
+
+        let gap = world.boids[1].position.x - world.boids[0].position.x;
+        assert!(gap < 0.4);
+    }
+
+    #[test]
+    fn alignment_matches_neighbor_headings() {
+        let mut world = World::new(10.0, 12);
+        world.steering_mut().separation.weight = 0.0;
+        world.steering_mut().cohesion.weight = 0.0;
+        world.add_boid_with_velocity(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        world.add_boid_with_velocity(Vec3::new(0.3, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let before = world.boids[0].velocity.angle_between(world.boids[1].velocity);
+        tick(&mut world);
+        let after = world.boids[0].velocity.angle_between(world.boids[1].velocity);
+
+        assert!(after < before);
+    }
+
+    #[test]
+    fn speed_never_exceeds_the_limit() {
+        let mut world = World::new(10.0, 12);
+        world.add_boid_with_velocity(Vec3::ZERO, Vec3::new(10.0, 0.0, 0.0));
+        world.add_boid(Vec3::new(0.01, 0.0, 0.0));
+
+        tick(&mut world);
+
+        let max_speed = world.steering().max_speed;
+        assert!(world.boids.iter().all(|b| b.velocity.length() <= max_speed + 1e-5));
+    }
+
+    #[test]
+    fn every_boid_is_hashed_into_the_cell_it_sits_in() {
+        let mut world = World::new(10.0, 10);
+        world.add_boid(Vec3::new(-4.5, -4.5, -4.5));
+        world.add_boid(Vec3::new(0.5, 0.5, 0.5));
+
+        assert_eq!(world.hash_table[0].boids_inside, vec![0]);
+        let center = 5 + 5 * 10 + 5 * 100;
+        assert_eq!(world.hash_table[center].boids_inside, vec![1]);
+        assert!(world.hash_table.iter().all(|c| c.boids_inside.len() <= 1));
+    }
+
+    #[test]
+    fn neighbor_queries_match_brute_force() {
+        let mut world = World::new(10.0, 8);
+        for i in 0..200 {
+            let t = i as f32;
+            let pos = Vec3::new((t * 0.37).sin(), (t * 0.71).cos(), (t * 0.13).sin()) * 4.9;
+            world.add_boid_with_velocity(pos, Vec3::new(t.cos(), t.sin(), 0.0));
+        }
+        for _ in 0..5 {
+            tick(&mut world);
+        }
+
+        for query in [Vec3::ZERO, Vec3::new(1.3, -2.0, 0.4), Vec3::splat(4.9)] {
+            for radius in [0.3, 1.0, 2.5] {
+                let expected: Vec<usize> = (0..world.boids.len())
+                    .filter(|&i| world.boids[i].position.distance(query) <= radius)
+                    .collect();
+                assert_eq!(world.neighbors_within(query, radius), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_boids_reenter_through_the_opposite_face() {
+        let mut world = World::new(10.0, 10);
+        world.set_boundary(Boundary::Wrap);
+        world.add_boid_with_velocity(Vec3::new(4.99, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        tick(&mut world);
+
+        let x = world.boids[0].position.x;
+        assert!((-5.0..-4.9).contains(&x), "boid ended up at x = {}", x);
+    }
+
+    #[test]
+    fn wrapped_neighbor_queries_see_across_the_seam() {
+        let mut world = World::new(10.0, 10);
+        world.add_boid(Vec3::new(-4.9, 0.0, 0.0));
+        world.add_boid(Vec3::new(4.9, 0.0, 0.0));
+
+        assert_eq!(world.neighbors_within(Vec3::new(4.95, 0.0, 0.0), 0.5), vec![1]);
+
+        world.set_boundary(Boundary::Wrap);
+        assert_eq!(world.neighbors_within(Vec3::new(4.95, 0.0, 0.0), 0.5), vec![0, 1]);
+        assert_eq!(world.neighbors_within(Vec3::new(-5.0, 0.0, 0.0), 0.2), vec![0, 1]);
+    }
+
+    #[test]
+    fn separation_acts_across_the_seam_when_wrapping() {
+        let mut world = World::new(10.0, 10);
+        world.set_boundary(Boundary::Wrap);
+        world.steering_mut().alignment.weight = 0.0;
+        world.steering_mut().cohesion.weight = 0.0;
+        world.add_boid(Vec3::new(-4.95, 0.0, 0.0));
+        world.add_boid(Vec3::new(4.95, 0.0, 0.0));
+
+        tick(&mut world);
+
+        assert!(world.boids[0].velocity.x > 0.0);
+        assert!(world.boids[1].velocity.x < 0.0);
+    }
+
+    #[test]
+    fn bouncing_boids_reflect_off_the_walls() {
+        let mut world = World::new(10.0, 10);
+        world.set_boundary(Boundary::Bounce);
+        world.add_boid_with_velocity(Vec3::new(0.0, -4.99, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        tick(&mut world);
+
+        let boid = world.boids[0];
+        assert!(boid.position.y >= -5.0);
+        assert!(boid.velocity.y > 0.0);
+    }
+
+    #[test]
+    fn steer_back_pushes_boids_away_from_nearby_walls() {
+        let mut world = World::new(10.0, 10);
+        world.set_boundary(Boundary::SteerBack { margin: 1.0, strength: 2.0 });
+        world.add_boid(Vec3::new(0.0, 0.0, 4.5));
+        world.add_boid(Vec3::new(-4.5, 0.0, -8.0));
+
+        tick(&mut world);
+
+        assert!(world.boids[0].velocity.z < 0.0);
+        assert_eq!(world.boids[0].velocity.x, 0.0);
+        assert!(world.boids[1].velocity.x > 0.0);
+        assert!(world.boids[1].velocity.z > 0.0);
+    }
+
+    fn run(seed: u64, ticks: usize) -> World {
+        let mut world = World::with_seed(10.0, 10, seed);
+        world.spawn_random(300, 1.0);
+        for _ in 0..ticks {
+            tick(&mut world);
+        }
+        world
+    }
+
+    #[test]
+    fn same_seed_gives_identical_runs() {
+        let a = run(42, 30);
+        let b = run(42, 30);
+
+        assert_eq!(a.tick(), 30);
+        assert_eq!(a.state_hash(), b.state_hash());
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        assert_ne!(run(1, 5).state_hash(), run(2, 5).state_hash());
+    }
+
+    #[test]
+    fn the_state_hash_tracks_every_tick() {
+        let world = run(7, 3);
+        let mut later = run(7, 3);
+        tick(&mut later);
+
+        assert_ne!(world.state_hash(), later.state_hash());
+    }
 }
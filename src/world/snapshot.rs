@@ -0,0 +1,449 @@
+use std::{fmt, fs, io, path::Path};
+
+use glam::Vec3;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use super::{Boid, Boundary, InvalidSetting, Steering, World};
+
+/// Bumped whenever the layout of `Snapshot` changes. Version 1 had no
+/// boid groups.
+const VERSION: u32 = 2;
+
+/// Binary snapshots start with this, which is how `World::load` tells them
+/// apart from RON ones.
+const MAGIC: &[u8; 4] = b"IRID";
+
+/// On-disk encoding of a world snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Human-readable RON, for sharing and hand-editing.
+    Ron,
+    /// Compact bincode, for checkpointing long runs.
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Ron(ron::Error),
+    Binary(bincode::Error),
+    UnsupportedVersion(u32),
+    /// The snapshot describes a world that can't be built.
+    Invalid(InvalidSetting),
+    /// A binary snapshot claims `count` boids but doesn't hold that many.
+    BoidCount { count: u64, bytes: usize },
+}
+
+/// Everything needed to resume a world exactly where it left off. The
+/// spatial hash is rebuilt on load rather than stored.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<B = SavedBoid> {
+    version: u32,
+    side_len: f32,
+    cells_per_side: usize,
+    steering: Steering,
+    boundary: Boundary,
+    seed: u64,
+    rng: Pcg32,
+    tick: u64,
+    boids: Vec<B>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedBoid {
+    position: Vec3,
+    velocity: Vec3,
+    acceleration: Vec3,
+    #[serde(default)]
+    group: u32,
+}
+
+/// Everything in a binary snapshot ahead of the boids themselves, ending
+/// with the count bincode writes before them. Decoding just this much lets
+/// the settings and the count be checked before anything is allocated for
+/// them.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    side_len: f32,
+    cells_per_side: usize,
+    steering: Steering,
+    boundary: Boundary,
+    seed: u64,
+    rng: Pcg32,
+    tick: u64,
+    boid_count: u64,
+}
+
+/// A boid as saved by version 1. RON snapshots from then load as the
+/// current version, but bincode can't skip missing fields.
+#[derive(Serialize, Deserialize)]
+struct SavedBoidV1 {
+    position: Vec3,
+    velocity: Vec3,
+    acceleration: Vec3,
+}
+
+impl Format {
+    /// RON for `.ron` files, binary for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ron") => Format::Ron,
+            _ => Format::Binary,
+        }
+    }
+}
+
+impl World {
+    /// Writes the world to `path`, as RON if it ends in `.ron` and in the
+    /// binary format otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        self.save_as(path, Format::from_path(path))
+    }
+
+    pub fn save_as(&self, path: impl AsRef<Path>, format: Format) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_bytes(format)?)?;
+        Ok(())
+    }
+
+    /// Reads a world written by `save`, in either format.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn to_bytes(&self, format: Format) -> Result<Vec<u8>, SnapshotError> {
+        let snapshot = self.snapshot();
+        match format {
+            Format::Ron => {
+                let config = ron::ser::PrettyConfig::new().struct_names(true);
+                Ok(ron::ser::to_string_pretty(&snapshot, config)?.into_bytes())
+            }
+            Format::Binary => {
+                let mut bytes = MAGIC.to_vec();
+                bincode::serialize_into(&mut bytes, &snapshot)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let snapshot: Snapshot = match bytes.strip_prefix(MAGIC) {
+            // the version comes first, so it can be checked before the
+            // rest is decoded with a layout it may not have
+            Some(body) => match bincode::deserialize::<u32>(body)? {
+                1 => {
+                    let boid = SavedBoidV1 { position: Vec3::ZERO, velocity: Vec3::ZERO, acceleration: Vec3::ZERO };
+                    check_header(body, bincode::serialized_size(&boid)?)?;
+                    bincode::deserialize::<Snapshot<SavedBoidV1>>(body)?.upgrade()
+                }
+                VERSION => {
+                    let boid = SavedBoid { position: Vec3::ZERO, velocity: Vec3::ZERO, acceleration: Vec3::ZERO, group: 0 };
+                    check_header(body, bincode::serialized_size(&boid)?)?;
+                    bincode::deserialize(body)?
+                }
+                version => return Err(SnapshotError::UnsupportedVersion(version)),
+            },
+            None => ron::de::from_bytes(bytes)?,
+        };
+        if snapshot.version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        World::check_settings(snapshot.side_len, snapshot.cells_per_side, &snapshot.steering, &snapshot.boundary)
+            .map_err(SnapshotError::Invalid)?;
+        Ok(Self::restore(snapshot))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: VERSION,
+            side_len: self.grid.side_len(),
+            cells_per_side: self.grid.cells_per_side,
+            steering: self.steering,
+            boundary: self.boundary,
+            seed: self.seed,
+            rng: self.rng.clone(),
+            tick: self.tick,
+            boids: self.boids.iter().map(|b| SavedBoid {
+                position: b.position,
+                velocity: b.velocity,
+                acceleration: b.acceleration,
+                group: b.group,
+            }).collect(),
+        }
+    }
+
+    fn restore(snapshot: Snapshot) -> Self {
+        let mut world = World::with_seed(snapshot.side_len, snapshot.cells_per_side, snapshot.seed);
+        world.steering = snapshot.steering;
+        world.boundary = snapshot.boundary;
+        world.rng = snapshot.rng;
+        world.tick = snapshot.tick;
+        for saved in snapshot.boids {
+            let mut boid = Boid::new(saved.position, saved.velocity, saved.group);
+            boid.acceleration = saved.acceleration;
+            world.boids.push(boid);
+        }
+        world.rebuild_hash();
+        world
+    }
+}
+
+/// Checks the settings at the start of a binary snapshot's `body`, and that
+/// it's exactly long enough for the boids it says it has at `boid_len`
+/// bytes each.
+fn check_header(body: &[u8], boid_len: u64) -> Result<(), SnapshotError> {
+    let header: Header = bincode::deserialize(body)?;
+    World::check_settings(header.side_len, header.cells_per_side, &header.steering, &header.boundary)
+        .map_err(SnapshotError::Invalid)?;
+    let len = header.boid_count.checked_mul(boid_len)
+        .and_then(|boids| boids.checked_add(bincode::serialized_size(&header).ok()?));
+    match len {
+        Some(len) if len == body.len() as u64 => Ok(()),
+        _ => Err(SnapshotError::BoidCount { count: header.boid_count, bytes: body.len() }),
+    }
+}
+
+impl Snapshot<SavedBoidV1> {
+    fn upgrade(self) -> Snapshot {
+        Snapshot {
+            version: self.version,
+            side_len: self.side_len,
+            cells_per_side: self.cells_per_side,
+            steering: self.steering,
+            boundary: self.boundary,
+            seed: self.seed,
+            rng: self.rng,
+            tick: self.tick,
+            boids: self.boids.into_iter().map(|b| SavedBoid {
+                position: b.position,
+                velocity: b.velocity,
+                acceleration: b.acceleration,
+                group: 0,
+            }).collect(),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "could not access snapshot: {}", e),
+            SnapshotError::Ron(e) => write!(f, "malformed RON snapshot: {}", e),
+            SnapshotError::Binary(e) => write!(f, "malformed binary snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "snapshot version {} is not supported (versions 1 to {} are)", v, VERSION)
+            }
+            SnapshotError::Invalid(e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::BoidCount { count, bytes } => {
+                write!(f, "truncated or corrupt snapshot: {} boids don't fit in {} bytes", count, bytes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<ron::Error> for SnapshotError {
+    fn from(e: ron::Error) -> Self {
+        SnapshotError::Ron(e)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(e: bincode::Error) -> Self {
+        SnapshotError::Binary(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::Vec3;
+
+    use super::{SavedBoidV1, Snapshot, MAGIC};
+    use crate::world::{Boundary, Format, InvalidSetting, SnapshotError, World};
+
+    fn busy_world() -> World {
+        let mut world = World::with_seed(10.0, 8, 99);
+        world.set_boundary(Boundary::Wrap);
+        world.steering_mut().cohesion.weight = 0.7;
+        world.spawn_random(100, 1.0);
+        for _ in 0..10 {
+            world.update(Duration::from_millis(16));
+        }
+        world
+    }
+
+    fn assert_resumes_exactly(format: Format) {
+        let mut original = busy_world();
+        let mut restored = World::from_bytes(&original.to_bytes(format).unwrap()).unwrap();
+
+        assert_eq!(restored.state_hash(), original.state_hash());
+        assert_eq!(restored.boundary(), original.boundary());
+        assert_eq!(restored.steering(), original.steering());
+
+        // the rng comes along too, so anything drawn afterwards matches
+        original.spawn_random(5, 1.0);
+        restored.spawn_random(5, 1.0);
+        for _ in 0..10 {
+            original.update(Duration::from_millis(16));
+            restored.update(Duration::from_millis(16));
+        }
+        assert_eq!(restored.state_hash(), original.state_hash());
+    }
+
+    #[test]
+    fn ron_snapshots_resume_exactly() {
+        assert_resumes_exactly(Format::Ron);
+    }
+
+    #[test]
+    fn binary_snapshots_resume_exactly() {
+        assert_resumes_exactly(Format::Binary);
+    }
+
+    #[test]
+    fn save_picks_the_format_from_the_extension() {
+        let world = busy_world();
+        let dir = std::env::temp_dir().join(format!("iridium-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ron_path = dir.join("flock.ron");
+        world.save(&ron_path).unwrap();
+        assert!(std::fs::read_to_string(&ron_path).unwrap().starts_with("Snapshot("));
+
+        let bin_path = dir.join("flock.bin");
+        world.save(&bin_path).unwrap();
+        assert_eq!(World::load(&bin_path).unwrap().state_hash(), world.state_hash());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let ron = String::from_utf8(busy_world().to_bytes(Format::Ron).unwrap()).unwrap();
+        let bumped = ron.replacen("version: 2", "version: 3", 1);
+
+        assert!(matches!(World::from_bytes(bumped.as_bytes()), Err(SnapshotError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn newer_binary_versions_are_rejected_before_decoding() {
+        let mut binary = busy_world().to_bytes(Format::Binary).unwrap();
+        binary[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&3u32.to_le_bytes());
+        assert!(matches!(World::from_bytes(&binary), Err(SnapshotError::UnsupportedVersion(3))));
+
+        // even when what follows is nothing like the current layout
+        let mut garbage = MAGIC.to_vec();
+        garbage.extend_from_slice(&7u32.to_le_bytes());
+        garbage.extend_from_slice(&[0xff; 3]);
+        assert!(matches!(World::from_bytes(&garbage), Err(SnapshotError::UnsupportedVersion(7))));
+    }
+
+    #[test]
+    fn groups_survive_a_round_trip() {
+        let mut world = World::new(10.0, 4);
+        world.add_boid_to_group(Vec3::ZERO, Vec3::X, 3);
+        for format in [Format::Ron, Format::Binary] {
+            let restored = World::from_bytes(&world.to_bytes(format).unwrap()).unwrap();
+            assert_eq!(restored.boids()[0].group(), 3);
+        }
+    }
+
+    #[test]
+    fn version_1_snapshots_still_load() {
+        let world = busy_world();
+        let current = world.snapshot();
+        let v1 = Snapshot {
+            version: 1,
+            side_len: current.side_len,
+            cells_per_side: current.cells_per_side,
+            steering: current.steering,
+            boundary: current.boundary,
+            seed: current.seed,
+            rng: current.rng,
+            tick: current.tick,
+            boids: current.boids.into_iter().map(|b| SavedBoidV1 {
+                position: b.position,
+                velocity: b.velocity,
+                acceleration: b.acceleration,
+            }).collect(),
+        };
+
+        let mut binary = MAGIC.to_vec();
+        bincode::serialize_into(&mut binary, &v1).unwrap();
+        assert_eq!(World::from_bytes(&binary).unwrap().state_hash(), world.state_hash());
+
+        let ron = ron::ser::to_string(&v1).unwrap();
+        assert_eq!(World::from_bytes(ron.as_bytes()).unwrap().state_hash(), world.state_hash());
+    }
+
+    fn binary(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, snapshot).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn impossible_worlds_are_rejected_rather_than_restored() {
+        let world = busy_world();
+        let ron = String::from_utf8(world.to_bytes(Format::Ron).unwrap()).unwrap();
+        let no_cells = ron.replacen("cells_per_side: 8", "cells_per_side: 0", 1);
+        assert!(matches!(
+            World::from_bytes(no_cells.as_bytes()),
+            Err(SnapshotError::Invalid(InvalidSetting::CellsPerSide(0)))
+        ));
+
+        let mut huge = world.snapshot();
+        huge.cells_per_side = 1 << 40;
+        assert!(matches!(
+            World::from_bytes(&binary(&huge)),
+            Err(SnapshotError::Invalid(InvalidSetting::CellsPerSide(_)))
+        ));
+
+        for side_len in [0.0, -5.0, f32::NAN] {
+            let mut flat = world.snapshot();
+            flat.side_len = side_len;
+            assert!(matches!(World::from_bytes(&binary(&flat)), Err(SnapshotError::Invalid(InvalidSetting::SideLength(_)))));
+        }
+
+        let mut blind = world.snapshot();
+        blind.steering.alignment.radius = -1.0;
+        assert!(matches!(World::from_bytes(&binary(&blind)), Err(SnapshotError::Invalid(InvalidSetting::Steering(..)))));
+
+        let mut unweighted = world.snapshot();
+        unweighted.steering.cohesion.weight = f32::NAN;
+        assert!(matches!(World::from_bytes(&binary(&unweighted)), Err(SnapshotError::Invalid(InvalidSetting::Weight(..)))));
+
+        let mut outwards = world.snapshot();
+        outwards.boundary = Boundary::SteerBack { margin: -1.0, strength: 2.0 };
+        assert!(matches!(
+            World::from_bytes(ron::ser::to_string(&outwards).unwrap().as_bytes()),
+            Err(SnapshotError::Invalid(InvalidSetting::Boundary("boundary margin", _)))
+        ));
+    }
+
+    #[test]
+    fn boid_counts_must_match_the_data() {
+        let world = busy_world();
+        let bytes = world.to_bytes(Format::Binary).unwrap();
+        let truncated = &bytes[..bytes.len() - 10];
+        assert!(matches!(World::from_bytes(truncated), Err(SnapshotError::BoidCount { count: 100, .. })));
+
+        // an empty world ends with its boid count, which a corrupt file
+        // might make enormous
+        let mut bogus = World::new(10.0, 4).to_bytes(Format::Binary).unwrap();
+        let at = bogus.len() - 8;
+        bogus[at..].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(World::from_bytes(&bogus), Err(SnapshotError::BoidCount { count: u64::MAX, .. })));
+    }
+}